@@ -0,0 +1,79 @@
+//! Check level files for problems before shipping them:
+//! `cargo run --bin levelcheck -- assets/levels/level_00.ron [--solve]`
+
+use std::process::ExitCode;
+
+use ludum_dare_54::check::check_level;
+use ludum_dare_54::load::LevelFile;
+use ludum_dare_54::palette::Palette;
+use ludum_dare_54::solver::{solve, SolverResult};
+
+const MAX_STATES: usize = 1_000_000;
+const PALETTE_PATH: &str = "assets/colors.palette.ron";
+
+fn main() -> ExitCode {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let run_solver = args.iter().any(|a| a == "--solve");
+    let paths = args
+        .iter()
+        .filter(|a| !a.starts_with("--"))
+        .collect::<Vec<_>>();
+    if paths.is_empty() {
+        eprintln!("Usage: levelcheck [--solve] <level.ron>...");
+        return ExitCode::FAILURE;
+    }
+
+    let palette = match std::fs::read_to_string(PALETTE_PATH)
+        .map_err(|e| e.to_string())
+        .and_then(|s| ron::from_str::<Palette>(&s).map_err(|e| e.to_string()))
+    {
+        Ok(palette) => palette,
+        Err(e) => {
+            eprintln!("{}: {}, using the built-in colors", PALETTE_PATH, e);
+            Palette::default()
+        }
+    };
+
+    let mut ok = true;
+    for path in paths {
+        let level = match std::fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|s| ron::from_str::<LevelFile>(&s).map_err(|e| e.to_string()))
+        {
+            Ok(level) => level,
+            Err(e) => {
+                println!("{}: {}", path, e);
+                ok = false;
+                continue;
+            }
+        };
+        let problems = check_level(&level, &palette);
+        for problem in problems.iter() {
+            println!("{}: {}", path, problem);
+        }
+        ok &= problems.is_empty();
+        if run_solver && problems.is_empty() {
+            match solve(&level, &palette, MAX_STATES) {
+                Ok(SolverResult::Solved(solution)) => {
+                    println!("{}: Solvable in at most {} moves", path, solution.moves)
+                }
+                Ok(SolverResult::Unsolvable { explored }) => {
+                    println!("{}: Unsolvable ({} arrangements explored)", path, explored);
+                    ok = false;
+                }
+                Ok(SolverResult::GaveUp { explored }) => {
+                    println!("{}: No solution within {} arrangements", path, explored)
+                }
+                Err(e) => {
+                    println!("{}: {}", path, e);
+                    ok = false;
+                }
+            }
+        }
+    }
+    if ok {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
@@ -6,6 +6,7 @@ use bevy::prelude::*;
 use bevy_easings::*;
 use bevy_mod_picking::prelude::*;
 
+use crate::controls::Selected;
 use crate::levels::LevelState;
 use crate::AppState;
 
@@ -20,10 +21,14 @@ impl Plugin for CameraMovePlugin {
                 animate_camera_direction.run_if(in_state(LevelState::MainMenu)),
             )
             .add_systems(OnEnter(AppState::Loading), spawn_camera)
-            .add_systems(PreUpdate, camera_rot.run_if(in_state(AppState::Level)))
+            .add_systems(
+                PreUpdate,
+                camera_rot.run_if(in_state(AppState::Level).or_else(in_state(AppState::Editor))),
+            )
             .add_systems(
                 PostUpdate,
-                (camera_move, camera_unobstruct).run_if(in_state(AppState::Level)),
+                (camera_move, camera_unobstruct)
+                    .run_if(in_state(AppState::Level).or_else(in_state(AppState::Editor))),
             );
     }
 }
@@ -104,20 +109,23 @@ fn camera_move(
     mut query: Query<&mut Transform, With<CameraDolly>>,
     camera: Query<&GlobalTransform, With<Camera3d>>,
     keys: Res<Input<KeyCode>>,
+    selected: Res<Selected>,
 ) {
     let camera = camera.single();
+    // The arrow keys move the selected block instead, if there is one
+    let arrow = |key| selected.0.is_none() && keys.pressed(key);
     for mut transform in query.iter_mut() {
         let mut input = Vec3::default();
-        if keys.pressed(KeyCode::W) || keys.pressed(KeyCode::Up) {
+        if keys.pressed(KeyCode::W) || arrow(KeyCode::Up) {
             input.z -= 1.0;
         }
-        if keys.pressed(KeyCode::S) || keys.pressed(KeyCode::Down) {
+        if keys.pressed(KeyCode::S) || arrow(KeyCode::Down) {
             input.z += 1.0;
         }
-        if keys.pressed(KeyCode::A) || keys.pressed(KeyCode::Left) {
+        if keys.pressed(KeyCode::A) || arrow(KeyCode::Left) {
             input.x -= 1.0;
         }
-        if keys.pressed(KeyCode::D) || keys.pressed(KeyCode::Right) {
+        if keys.pressed(KeyCode::D) || arrow(KeyCode::Right) {
             input.x += 1.0;
         }
         if input.length_squared() < 0.001 {
@@ -0,0 +1,261 @@
+//! Sanity checks for level files, beyond what is needed to load them.
+
+use std::collections::HashMap;
+
+use crate::cubes::CubeColor;
+use crate::game::Dis2;
+use crate::load::{level_parse, level_surround, LevelFile, LevelLoadError, Object, Tile};
+use crate::objects::ObjectShape;
+use crate::palette::Palette;
+use crate::solver::Puzzle;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LevelProblem {
+    Unloadable(LevelLoadError),
+    RaggedRow {
+        row: usize,
+        length: usize,
+        width: usize,
+    },
+    NoAdjacentFloor {
+        row: usize,
+        column: usize,
+    },
+    BeltOverWall {
+        row: usize,
+        column: usize,
+    },
+    BeltOverlap {
+        row: usize,
+        column: usize,
+    },
+    UnusedObject {
+        index: usize,
+    },
+    NoInput {
+        color: CubeColor,
+    },
+    /// The belts pass cubes around in a loop before the player has moved anything.
+    BeltCycle,
+}
+
+impl std::fmt::Display for LevelProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LevelProblem::Unloadable(e) => write!(f, "{}", e),
+            LevelProblem::RaggedRow { row, length, width } => write!(
+                f,
+                "Row {} has {} tiles, but the level is {} tiles wide",
+                row, length, width
+            ),
+            LevelProblem::NoAdjacentFloor { row, column } => write!(
+                f,
+                "The input/output at row {}, column {} has no adjacent floor",
+                row, column
+            ),
+            LevelProblem::BeltOverWall { row, column } => {
+                write!(
+                    f,
+                    "A belt covers the wall at row {}, column {}",
+                    row, column
+                )
+            }
+            LevelProblem::BeltOverlap { row, column } => {
+                write!(f, "Belts overlap at row {}, column {}", row, column)
+            }
+            LevelProblem::UnusedObject { index } => write!(f, "Object {} is never used", index),
+            LevelProblem::NoInput { color } => {
+                write!(f, "There is a {} output but no {} input", color, color)
+            }
+            LevelProblem::BeltCycle => write!(f, "The belts form a cycle"),
+        }
+    }
+}
+
+pub fn check_level(file: &LevelFile, palette: &Palette) -> Vec<LevelProblem> {
+    let mut layout = match level_parse(file, palette) {
+        Ok(layout) => layout,
+        Err(e) => return vec![LevelProblem::Unloadable(e)],
+    };
+    level_surround(&mut layout);
+    let mut problems = vec![];
+
+    let width = layout.iter().map(|row| row.len()).max().unwrap_or_default();
+    for (row, tiles) in layout.iter().enumerate() {
+        if tiles.len() != width {
+            problems.push(LevelProblem::RaggedRow {
+                row,
+                length: tiles.len(),
+                width,
+            });
+        }
+    }
+
+    let get = |dis: Dis2| {
+        let (x, z): (isize, isize) = dis.into();
+        if x < 0 || z < 0 {
+            return None;
+        }
+        layout.get(z as usize).and_then(|row| row.get(x as usize))
+    };
+
+    let mut belts: HashMap<Dis2, usize> = HashMap::new();
+    let mut inputs = vec![];
+    let mut outputs = vec![];
+    for (row, tiles) in layout.iter().enumerate() {
+        for (column, tile) in tiles.iter().enumerate() {
+            let dis: Dis2 = (column, row).into();
+            match tile {
+                Tile::Input(_, _) | Tile::Output(_, _) => {
+                    let floor = [Dis2::X, Dis2::NEG_X, Dis2::Z, Dis2::NEG_Z]
+                        .iter()
+                        .any(|d| get(dis + *d).is_some_and(|t| t.is_floor()));
+                    if !floor {
+                        problems.push(LevelProblem::NoAdjacentFloor { row, column });
+                    }
+                    if let Tile::Input(_, settings) = tile {
+                        inputs.extend(settings.colors.iter().copied());
+                    } else if let Tile::Output(_, color) = tile {
+                        outputs.push(*color);
+                    }
+                }
+                Tile::Floor(object) if *object != Object::Empty => {
+                    if let Object::Painter(_, to) = object.unlocked() {
+                        // Painted cubes can feed an output just like an input can
+                        inputs.push(*to);
+                    }
+                    // Objects have already been validated by `level_parse`
+                    for tile in ObjectShape::new(object).unwrap().tiles {
+                        *belts.entry(dis + tile).or_default() += 1;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let mut belts = belts.into_iter().collect::<Vec<_>>();
+    belts.sort();
+    for (dis, count) in belts {
+        let (column, row): (isize, isize) = dis.into();
+        let (row, column) = (row as usize, column as usize);
+        if !get(dis).is_some_and(|t| t.is_floor()) {
+            problems.push(LevelProblem::BeltOverWall { row, column });
+        }
+        if count > 1 {
+            problems.push(LevelProblem::BeltOverlap { row, column });
+        }
+    }
+
+    for index in 0..file.objects.len() {
+        let digit = char::from_digit(index as u32, 10);
+        let used = file
+            .layout
+            .iter()
+            .any(|row| row.chars().any(|c| Some(c) == digit));
+        if !used {
+            problems.push(LevelProblem::UnusedObject { index });
+        }
+    }
+
+    for color in outputs {
+        if !inputs.contains(&color) && !problems.contains(&LevelProblem::NoInput { color }) {
+            problems.push(LevelProblem::NoInput { color });
+        }
+    }
+
+    // Overlapping belts can't be placed, so only look for loops in an otherwise sound layout
+    if problems.is_empty() && Puzzle::new(file, palette).is_ok_and(|p| p.has_cycle()) {
+        problems.push(LevelProblem::BeltCycle);
+    }
+
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unknown_color() {
+        let file = LevelFile {
+            layout: vec!["#o#".to_string(), "# #".to_string(), "#i#".to_string()],
+            objects: vec![],
+            par: None,
+            objectives: vec![],
+            inputs: Default::default(),
+            outputs: [((1, 0), CubeColor::new("Purpel"))].into_iter().collect(),
+            kicks: None,
+        };
+        assert_eq!(
+            check_level(&file, &Palette::default()),
+            vec![LevelProblem::Unloadable(LevelLoadError::UnknownColor {
+                row: 0,
+                column: 1,
+                name: "Purpel".to_string()
+            })]
+        );
+    }
+
+    #[test]
+    fn test_belt_cycle() {
+        let file = LevelFile {
+            layout: vec![
+                "#o###".to_string(),
+                "#   #".to_string(),
+                "#0  #".to_string(),
+                "#   #".to_string(),
+                "#i###".to_string(),
+            ],
+            objects: vec![Object::Belt("rrrr".to_string())],
+            par: None,
+            objectives: vec![],
+            inputs: Default::default(),
+            outputs: Default::default(),
+            kicks: None,
+        };
+        assert_eq!(
+            check_level(&file, &Palette::default()),
+            vec![LevelProblem::BeltCycle]
+        );
+    }
+
+    #[test]
+    fn test_check_level() {
+        let file = LevelFile {
+            layout: vec![
+                "#O##o#".to_string(),
+                "# 0  ".to_string(),
+                "#2  1#".to_string(),
+                "##i###".to_string(),
+            ],
+            objects: vec![
+                Object::Belt("rff".to_string()),
+                Object::Belt("ff".to_string()),
+                Object::Belt("lf".to_string()),
+                Object::Empty,
+            ],
+            par: None,
+            objectives: vec![],
+            inputs: Default::default(),
+            outputs: Default::default(),
+            kicks: None,
+        };
+        assert_eq!(
+            check_level(&file, &Palette::default()),
+            vec![
+                LevelProblem::RaggedRow {
+                    row: 1,
+                    length: 5,
+                    width: 6
+                },
+                LevelProblem::BeltOverWall { row: 2, column: 0 },
+                LevelProblem::BeltOverlap { row: 1, column: 4 },
+                LevelProblem::UnusedObject { index: 3 },
+                LevelProblem::NoInput {
+                    color: CubeColor::GREEN
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,259 @@
+//! Playing without a mouse: a selected block, cycled through with tab and moved and rotated with
+//! the keyboard or a gamepad.
+
+use std::f32::consts::PI;
+
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use bevy_mod_picking::prelude::*;
+
+use crate::game::{Block, Dis2, Level, Lock, MoveBlock, RotateBlock, Turn};
+use crate::ui::ShowVictory;
+use crate::AppState;
+
+pub struct ControlsPlugin;
+
+impl Plugin for ControlsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Selected>()
+            .add_systems(
+                Update,
+                (
+                    release_selection,
+                    cycle_selection,
+                    move_selected,
+                    rotate_selected,
+                    draw_selection.run_if(resource_exists::<Level>()),
+                )
+                    .chain()
+                    .run_if(in_state(AppState::Level)),
+            )
+            .add_systems(OnEnter(AppState::Unloading), clear_selection);
+    }
+}
+
+/// The block moved with the keyboard or a gamepad, if any. The arrow keys move it instead of the
+/// camera while it is selected.
+#[derive(Resource, Default)]
+pub struct Selected(pub Option<Entity>);
+
+const SELECTION_COLOR: Color = Color::rgb(1.0, 0.85, 0.1);
+
+/// The keyboard and all connected gamepads.
+#[derive(SystemParam)]
+struct Controls<'w> {
+    keys: Res<'w, Input<KeyCode>>,
+    gamepads: Res<'w, Gamepads>,
+    buttons: Res<'w, Input<GamepadButton>>,
+}
+
+impl Controls<'_> {
+    fn button(&self, button: GamepadButtonType) -> bool {
+        self.gamepads
+            .iter()
+            .any(|g| self.buttons.just_pressed(GamepadButton::new(g, button)))
+    }
+
+    fn just_pressed(&self, key: KeyCode, button: GamepadButtonType) -> bool {
+        self.keys.just_pressed(key) || self.button(button)
+    }
+
+    fn shift(&self) -> bool {
+        self.keys
+            .any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight])
+    }
+}
+
+/// Give the arrow keys back to the camera once the player picks up the mouse or wins.
+fn release_selection(
+    mut drags: EventReader<Pointer<DragStart>>,
+    victory: Res<ShowVictory>,
+    mut selected: ResMut<Selected>,
+) {
+    if drags.iter().count() > 0 || victory.won() {
+        selected.0 = None;
+    }
+}
+
+fn cycle_selection(
+    controls: Controls,
+    blocks: Query<(Entity, &Block)>,
+    mut selected: ResMut<Selected>,
+) {
+    if controls.just_pressed(KeyCode::Escape, GamepadButtonType::Select) {
+        selected.0 = None;
+        return;
+    }
+    let step = if controls.keys.just_pressed(KeyCode::Tab) {
+        if controls.shift() {
+            -1
+        } else {
+            1
+        }
+    } else if controls.button(GamepadButtonType::RightTrigger) {
+        1
+    } else if controls.button(GamepadButtonType::LeftTrigger) {
+        -1
+    } else {
+        return;
+    };
+    // Fixed blocks can't be moved, so there is no point in selecting them
+    let mut movable = blocks
+        .iter()
+        .filter(|(_, b)| b.lock != Lock::Fixed)
+        .map(|(e, b)| (b.index, e))
+        .collect::<Vec<_>>();
+    if movable.is_empty() {
+        selected.0 = None;
+        return;
+    }
+    movable.sort();
+    let current = selected
+        .0
+        .and_then(|s| movable.iter().position(|(_, e)| *e == s));
+    selected.0 = Some(movable[cycle_index(current, step, movable.len())].1);
+}
+
+/// The index `step` places on from `current`, wrapping around. With nothing selected yet, stepping
+/// forwards starts at the first index and stepping backwards at the last.
+fn cycle_index(current: Option<usize>, step: isize, len: usize) -> usize {
+    match current {
+        Some(i) => (i as isize + step).rem_euclid(len as isize) as usize,
+        None if step > 0 => 0,
+        None => len - 1,
+    }
+}
+
+fn move_selected(
+    controls: Controls,
+    selected: Res<Selected>,
+    blocks: Query<&Block>,
+    camera: Query<&GlobalTransform, With<Camera3d>>,
+    mut moves: EventWriter<MoveBlock>,
+) {
+    let Some((entity, block)) = selected.0.and_then(|e| blocks.get(e).ok().map(|b| (e, b))) else {
+        return;
+    };
+    let mut input = Vec3::ZERO;
+    for (key, button, dir) in [
+        (KeyCode::Up, GamepadButtonType::DPadUp, Vec3::NEG_Z),
+        (KeyCode::Down, GamepadButtonType::DPadDown, Vec3::Z),
+        (KeyCode::Left, GamepadButtonType::DPadLeft, Vec3::NEG_X),
+        (KeyCode::Right, GamepadButtonType::DPadRight, Vec3::X),
+    ] {
+        if controls.just_pressed(key, button) {
+            input += dir;
+        }
+    }
+    if input == Vec3::ZERO {
+        return;
+    }
+    let yaw = camera.get_single().map_or(0.0, |c| {
+        c.to_scale_rotation_translation()
+            .1
+            .to_euler(EulerRot::YXZ)
+            .0
+    });
+    moves.send(MoveBlock {
+        entity,
+        target: block.position() + grid_step(yaw, input),
+    });
+}
+
+/// The grid step for a direction on screen, given the yaw of the camera. The camera looks at the
+/// level diagonally, so each direction moves along the grid axis an eighth of a turn to its left
+/// on screen.
+fn grid_step(yaw: f32, input: Vec3) -> Dis2 {
+    let dir = Quat::from_rotation_y(yaw + PI * 0.25) * input;
+    if dir.x.abs() > dir.z.abs() {
+        Dis2::new(dir.x.signum() as isize, 0)
+    } else {
+        Dis2::new(0, dir.z.signum() as isize)
+    }
+}
+
+fn rotate_selected(
+    controls: Controls,
+    selected: Res<Selected>,
+    mut rotate: EventWriter<RotateBlock>,
+) {
+    let Some(entity) = selected.0 else {
+        return;
+    };
+    let turn = if controls.just_pressed(KeyCode::R, GamepadButtonType::South) {
+        if controls.shift() {
+            Turn::Right
+        } else {
+            Turn::Left
+        }
+    } else if controls.button(GamepadButtonType::East) {
+        Turn::Right
+    } else {
+        return;
+    };
+    rotate.send(RotateBlock { entity, turn });
+}
+
+fn draw_selection(
+    mut selected: ResMut<Selected>,
+    blocks: Query<&Block>,
+    level: Res<Level>,
+    mut gizmos: Gizmos,
+) {
+    let Some(entity) = selected.0 else {
+        return;
+    };
+    let Ok(block) = blocks.get(entity) else {
+        selected.0 = None;
+        return;
+    };
+    for tile in block.iter() {
+        let center = level.to_vec3(tile) + Vec3::Y * 1.05;
+        let corners = [
+            (-1.0, -1.0),
+            (1.0, -1.0),
+            (1.0, 1.0),
+            (-1.0, 1.0),
+            (-1.0, -1.0),
+        ];
+        gizmos.linestrip(
+            corners.map(|(x, z)| center + Vec3::new(x, 0.0, z) * 0.48),
+            SELECTION_COLOR,
+        );
+    }
+}
+
+fn clear_selection(mut selected: ResMut<Selected>) {
+    selected.0 = None;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cycle_index() {
+        assert_eq!(cycle_index(None, 1, 3), 0);
+        assert_eq!(cycle_index(None, -1, 3), 2);
+        assert_eq!(cycle_index(Some(0), 1, 3), 1);
+        assert_eq!(cycle_index(Some(2), 1, 3), 0);
+        assert_eq!(cycle_index(Some(0), -1, 3), 2);
+        assert_eq!(cycle_index(Some(0), 1, 1), 0);
+    }
+
+    #[test]
+    fn test_grid_step() {
+        // A camera an eighth of a turn to the right lines the screen up with the grid
+        let yaw = -PI * 0.25;
+        assert_eq!(grid_step(yaw, Vec3::NEG_Z), Dis2::new(0, -1));
+        assert_eq!(grid_step(yaw, Vec3::X), Dis2::new(1, 0));
+        assert_eq!(grid_step(yaw, Vec3::Z), Dis2::new(0, 1));
+        assert_eq!(grid_step(yaw, Vec3::NEG_X), Dis2::new(-1, 0));
+        // A quarter turn of the camera turns every step with it
+        assert_eq!(grid_step(yaw + PI * 0.5, Vec3::NEG_Z), Dis2::new(-1, 0));
+        assert_eq!(grid_step(yaw - PI * 0.5, Vec3::NEG_Z), Dis2::new(1, 0));
+        // Anywhere short of the diagonal snaps to the nearest axis
+        assert_eq!(grid_step(yaw + PI * 0.2, Vec3::NEG_Z), Dis2::new(0, -1));
+        assert_eq!(grid_step(yaw + PI * 0.3, Vec3::NEG_Z), Dis2::new(-1, 0));
+    }
+}
@@ -1,11 +1,17 @@
 #![allow(clippy::type_complexity)]
 
+use std::collections::HashMap;
+use std::sync::Mutex;
 use std::time::Duration;
 
 use bevy::prelude::*;
-use bevy_easings::*;
+use serde::{Deserialize, Serialize};
 
+use crate::game::{Dis2, Level};
 use crate::levels::LevelEntity;
+use crate::objectives::{update_objectives, Objectives};
+use crate::palette::{Palette, Tint};
+use crate::routing::Routing;
 use crate::ui::ShowVictory;
 use crate::AppState;
 
@@ -14,10 +20,31 @@ pub struct CubePlugin;
 impl Plugin for CubePlugin {
     fn build(&self, app: &mut App) {
         app.add_event::<CubeRecieved>()
-            .add_systems(PreUpdate, route_cubes)
+            .add_event::<CubeRejected>()
+            .add_event::<SimulationEvent>()
+            .init_resource::<CubeSlots>()
+            .init_resource::<Simulation>()
+            .add_systems(
+                FixedUpdate,
+                (tick_clock, move_cubes, cube_spawner).chain().run_if(
+                    in_state(AppState::Level)
+                        .and_then(resource_exists::<Level>())
+                        .and_then(simulation_running),
+                ),
+            )
+            .add_systems(OnEnter(AppState::Level), reset_simulation)
             .add_systems(
                 Update,
-                (process_cubes, cube_spawner, check_connection).run_if(in_state(AppState::Level)),
+                (
+                    interpolate_cubes,
+                    cube_scenes,
+                    reject_sound,
+                    check_connection.after(update_objectives),
+                    simulation_keys,
+                    apply_simulation.after(simulation_keys),
+                    step_simulation.after(apply_simulation),
+                )
+                    .run_if(in_state(AppState::Level)),
             );
     }
 }
@@ -25,36 +52,159 @@ impl Plugin for CubePlugin {
 #[derive(Event)]
 struct CubeRecieved;
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
-pub enum CubeColor {
-    Green,
-    Purple,
-    Yellow,
-    Black,
+/// A cube of the wrong color reached an output.
+#[derive(Event)]
+struct CubeRejected;
+
+/// The length of a simulation tick, in seconds.
+pub const TICK: f32 = 1.0 / 60.0;
+/// The time a cube takes to move half a tile, in seconds.
+const SEGMENT_TIME: f32 = 0.75;
+
+/// The speed of the cube simulation, which can also be paused and stepped through.
+#[derive(Resource, Debug)]
+pub struct Simulation {
+    paused: bool,
+    /// How many times faster than real time the simulation runs.
+    speed: u32,
+    /// The steps requested while paused, not yet taken.
+    steps: usize,
+    stepping: bool,
+    /// The simulated time, in seconds.
+    elapsed: f32,
 }
 
-impl CubeColor {
-    pub fn cube_path(&self) -> &str {
-        match self {
-            CubeColor::Green => "models/cubeG.glb#Scene0",
-            CubeColor::Purple => "models/cubeP.glb#Scene0",
-            CubeColor::Yellow => "models/cubeY.glb#Scene0",
-            CubeColor::Black => "models/cubeB.glb#Scene0",
+impl Default for Simulation {
+    fn default() -> Self {
+        Self {
+            paused: false,
+            speed: 1,
+            steps: 0,
+            stepping: false,
+            elapsed: 0.0,
         }
     }
+}
+
+impl Simulation {
+    /// The simulated time, which is what objectives are measured in.
+    pub fn elapsed(&self) -> f32 {
+        self.elapsed
+    }
+
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
 
-    pub fn io_path(&self) -> &str {
-        match self {
-            CubeColor::Green => "models/inoutG.glb#Scene0",
-            CubeColor::Purple => "models/inoutP.glb#Scene0",
-            CubeColor::Yellow => "models/inoutY.glb#Scene0",
-            CubeColor::Black => "models/inoutB.glb#Scene0",
+    pub fn speed(&self) -> u32 {
+        self.speed
+    }
+}
+
+#[derive(Event, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SimulationEvent {
+    TogglePause,
+    Speed(u32),
+    /// Pause, and move the cubes forward by half a tile.
+    Step,
+}
+
+/// A cube color, identified by its name in the palette. In level files it is written as a bare
+/// name, like `Green`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct CubeColor(&'static str);
+
+impl CubeColor {
+    pub const GREEN: CubeColor = CubeColor("Green");
+    pub const PURPLE: CubeColor = CubeColor("Purple");
+    pub const YELLOW: CubeColor = CubeColor("Yellow");
+    pub const BLACK: CubeColor = CubeColor("Black");
+
+    pub fn new(name: &str) -> Self {
+        // Names are interned, so that colors can stay `Copy`
+        static NAMES: Mutex<Vec<&'static str>> = Mutex::new(vec![]);
+        let mut names = NAMES.lock().unwrap();
+        if let Some(interned) = names.iter().find(|n| **n == name) {
+            return CubeColor(interned);
         }
+        let interned: &'static str = Box::leak(name.into());
+        names.push(interned);
+        CubeColor(interned)
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.0
+    }
+}
+
+impl std::fmt::Display for CubeColor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.0)
+    }
+}
+
+impl Serialize for CubeColor {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_unit_variant("CubeColor", 0, self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for CubeColor {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        // Read as a unit variant of an enum with any variants, to match how it is serialized
+        deserializer.deserialize_enum("CubeColor", &[], ColorVisitor)
+    }
+}
+
+struct ColorVisitor;
+
+impl<'de> serde::de::Visitor<'de> for ColorVisitor {
+    type Value = CubeColor;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("a color name")
+    }
+
+    fn visit_str<E: serde::de::Error>(self, name: &str) -> Result<CubeColor, E> {
+        Ok(CubeColor::new(name))
+    }
+
+    fn visit_enum<A: serde::de::EnumAccess<'de>>(self, data: A) -> Result<CubeColor, A::Error> {
+        use serde::de::VariantAccess;
+        let (color, variant) = data.variant_seed(ColorVisitor)?;
+        variant.unit_variant()?;
+        Ok(color)
+    }
+}
+
+impl<'de> serde::de::DeserializeSeed<'de> for ColorVisitor {
+    type Value = CubeColor;
+
+    fn deserialize<D: serde::Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<CubeColor, D::Error> {
+        deserializer.deserialize_identifier(self)
     }
 }
 
 #[derive(Component, Clone, Copy, Debug)]
-pub struct Cube(CubeColor);
+pub struct Cube {
+    color: CubeColor,
+    /// The path of the parent router the cube follows.
+    path: usize,
+    /// The point of the path the cube is at, or moving to.
+    point: usize,
+    /// How far the cube has moved from the previous point, from 0 to 1.
+    progress: f32,
+    /// The simulated positions at the previous and the latest tick, relative to the router.
+    previous: Vec3,
+    position: Vec3,
+}
+
+/// The cube on each tile, as no two cubes can share a tile.
+#[derive(Resource, Default)]
+pub struct CubeSlots(HashMap<Dis2, Entity>);
 
 #[derive(Component, Debug)]
 pub struct CubeProcessor {
@@ -66,171 +216,541 @@ impl CubeProcessor {
     pub fn new(color: CubeColor) -> Self {
         Self { count: 0, color }
     }
+
+    /// The number of cubes of the right color received.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+}
+
+/// Repaints cubes of one color to another, letting other cubes pass unchanged.
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CubePainter {
+    pub from: CubeColor,
+    pub to: CubeColor,
+}
+
+impl CubePainter {
+    pub fn paint(&self, color: CubeColor) -> CubeColor {
+        if color == self.from {
+            self.to
+        } else {
+            color
+        }
+    }
+}
+
+/// How an input spawns cubes, as set in the level file.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SpawnSettings {
+    /// The time between cubes, in seconds.
+    #[serde(default = "default_delay")]
+    pub delay: f32,
+    /// The time before the first cube, in seconds.
+    #[serde(default)]
+    pub offset: f32,
+    /// The number of cubes to spawn before stopping, if the input ever stops.
+    #[serde(default)]
+    pub count: Option<usize>,
+    /// The colors of the cubes, repeating in order. Left empty, the color of the input tile is used.
+    #[serde(default)]
+    pub colors: Vec<CubeColor>,
+}
+
+fn default_delay() -> f32 {
+    2.0
+}
+
+impl SpawnSettings {
+    /// An input spawning cubes of one color forever.
+    pub fn new(color: CubeColor) -> Self {
+        Self {
+            delay: default_delay(),
+            offset: 0.0,
+            count: None,
+            colors: vec![color],
+        }
+    }
 }
 
 #[derive(Component)]
 pub struct CubeSpawner {
     pos: Vec3,
-    delay: f32,
-    next: f32,
-    color: CubeColor,
+    settings: SpawnSettings,
+    /// The time left until the next cube is spawned.
+    cooldown: f32,
+    spawned: usize,
 }
 
 impl CubeSpawner {
-    pub fn new(pos: Vec3, delay: f32, color: CubeColor) -> Self {
+    pub fn new(pos: Vec3, settings: SpawnSettings) -> Self {
         Self {
             pos,
-            delay,
-            next: 0.0,
-            color,
+            cooldown: settings.offset,
+            settings,
+            spawned: 0,
         }
     }
 }
 
+/// The paths cubes take through an object, each starting at an entry and ending at an exit.
 #[derive(Component)]
-pub struct CubeRouter(pub Vec<Vec3>);
+pub struct CubeRouter {
+    paths: Vec<Vec<Vec3>>,
+    /// Cubes entering through the same entry take turns between its paths.
+    turn: usize,
+}
+
+impl CubeRouter {
+    pub fn new(path: Vec<Vec3>) -> Self {
+        Self::with_paths(vec![path])
+    }
+
+    pub fn with_paths(paths: Vec<Vec<Vec3>>) -> Self {
+        Self { paths, turn: 0 }
+    }
+
+    pub fn paths(&self) -> &[Vec<Vec3>] {
+        &self.paths
+    }
+}
 
-fn route_cubes(
-    query: Query<(Entity, &Transform), (With<Cube>, Without<Parent>)>,
-    routers: Query<(Entity, &CubeRouter, &GlobalTransform)>,
+/// Whether a point of a router path is in the middle of a tile, rather than on its edge.
+fn is_center(point: Vec3) -> bool {
+    (point.x - point.x.round()).abs() < 0.1 && (point.z - point.z.round()).abs() < 0.1
+}
+
+/// The tile a cube is on: the tile of the center it is at, or moving to or away from.
+fn cube_tile(path: &[Vec3], point: usize, global: &GlobalTransform, level: &Level) -> Dis2 {
+    let center = if point == 0 || is_center(path[point]) {
+        path[point]
+    } else {
+        path[point - 1]
+    };
+    level.to_discrete(global.transform_point(center))
+}
+
+/// Move the cubes along their paths, handing them over to the next router at the end of a path.
+/// A cube only moves onto a tile once it is free, otherwise it waits where it is.
+#[allow(clippy::too_many_arguments)]
+fn move_cubes(
+    mut cubes: Query<(Entity, &mut Cube, &Parent)>,
+    mut routers: Query<(Entity, &mut CubeRouter, &GlobalTransform)>,
+    mut processors: Query<&mut CubeProcessor>,
+    painters: Query<&CubePainter>,
+    level: Res<Level>,
+    mut slots: ResMut<CubeSlots>,
+    mut received: EventWriter<CubeRecieved>,
+    mut rejected: EventWriter<CubeRejected>,
     mut cmds: Commands,
 ) {
-    for (entity, transform) in query.iter() {
-        let mut parent = false;
-        for (rent, router, rtrans) in routers.iter() {
-            let pos = rtrans.transform_point(router.0[0]);
-            if (transform.translation - pos).length_squared() < 0.01 {
-                cmds.entity(rent).add_child(entity);
-                let eases = transform.with_translation(router.0[0]).ease_to(
-                    transform.with_translation(router.0[1]),
-                    EaseMethod::Linear,
-                    EasingType::Once {
-                        duration: Duration::from_millis(750),
-                    },
-                );
-                if router.0.len() > 2 {
-                    let mut eases = eases.ease_to(
-                        transform.with_translation(router.0[2]),
-                        EaseMethod::Linear,
-                        EasingType::Once {
-                            duration: Duration::from_millis(750),
-                        },
-                    );
-                    for p in router.0.iter().skip(3) {
-                        eases = eases.ease_to(
-                            transform.with_translation(*p),
-                            EaseMethod::Linear,
-                            EasingType::Once {
-                                duration: Duration::from_millis(750),
-                            },
-                        );
-                    }
-                    cmds.entity(entity).insert(eases);
-                } else {
-                    cmds.entity(entity).insert(eases);
+    slots.0.clear();
+    for (entity, cube, parent) in cubes.iter() {
+        if let Ok((_, router, global)) = routers.get(parent.get()) {
+            let tile = cube_tile(&router.paths[cube.path], cube.point, global, &level);
+            slots.0.insert(tile, entity);
+        }
+    }
+
+    // Always move the cubes in the same order, so the simulation is deterministic
+    let mut order = cubes.iter().map(|(e, _, _)| e).collect::<Vec<_>>();
+    order.sort();
+    let step = TICK / SEGMENT_TIME;
+    for entity in order {
+        let (_, mut cube, parent) = cubes.get_mut(entity).unwrap();
+        cube.previous = cube.position;
+        let Ok((_, router, global)) = routers.get(parent.get()) else {
+            cmds.entity(entity).despawn_recursive();
+            continue;
+        };
+        let path = router.paths[cube.path].clone();
+        let global = *global;
+
+        if cube.progress < 1.0 {
+            cube.progress = (cube.progress + step).min(1.0);
+            cube.position = path[cube.point - 1].lerp(path[cube.point], cube.progress);
+            continue;
+        }
+        let tile = cube_tile(&path, cube.point, &global, &level);
+
+        // Follow the current path
+        if cube.point + 1 < path.len() {
+            let to = path[cube.point + 1];
+            if is_center(to) {
+                let next = level.to_discrete(global.transform_point(to));
+                if slots.0.contains_key(&next) {
+                    continue;
                 }
-                parent = true;
-                break;
+                slots.0.remove(&tile);
+                slots.0.insert(next, entity);
             }
+            cube.point += 1;
+            cube.progress = step;
+            cube.position = path[cube.point - 1].lerp(to, step);
+            continue;
         }
-        if !parent {
+
+        if let Ok(mut proc) = processors.get_mut(parent.get()) {
+            if cube.color == proc.color {
+                proc.count += 1;
+                received.send(CubeRecieved);
+            } else {
+                rejected.send(CubeRejected);
+            }
+            slots.0.remove(&tile);
             cmds.entity(entity).despawn_recursive();
+            continue;
         }
+
+        // Hand the cube over to the router with a path starting where this one ends
+        let exit = global.transform_point(path[cube.point]);
+        let next = routers.iter().find_map(|(rent, router, rglobal)| {
+            let entries = router
+                .paths
+                .iter()
+                .enumerate()
+                .filter(|(_, p)| rglobal.transform_point(p[0]).distance_squared(exit) < 0.01)
+                .map(|(i, _)| i)
+                .collect::<Vec<_>>();
+            if rent == parent.get() || entries.is_empty() {
+                return None;
+            }
+            let i = entries[router.turn % entries.len()];
+            let tile = level.to_discrete(rglobal.transform_point(router.paths[i][1]));
+            Some((rent, i, tile))
+        });
+        let Some((rent, i, next)) = next else {
+            // The cube falls off the end of the belt
+            slots.0.remove(&tile);
+            cmds.entity(entity).despawn_recursive();
+            continue;
+        };
+        if slots.0.contains_key(&next) {
+            continue;
+        }
+        slots.0.remove(&tile);
+        slots.0.insert(next, entity);
+
+        if let Ok(painter) = painters.get(parent.get()) {
+            cube.color = painter.paint(cube.color);
+        }
+        let (_, mut router, _) = routers.get_mut(rent).unwrap();
+        router.turn += 1;
+        cube.path = i;
+        cube.point = 1;
+        cube.progress = step;
+        cube.previous = router.paths[i][0];
+        cube.position = router.paths[i][0].lerp(router.paths[i][1], step);
+        cmds.entity(rent).add_child(entity);
     }
 }
 
-fn process_cubes(
-    query: Query<
-        (Entity, &Parent, &Cube),
-        (
-            Without<EasingComponent<Transform>>,
-            Without<EasingChainComponent<Transform>>,
-        ),
-    >,
-    mut processors: Query<&mut CubeProcessor>,
-    mut event: EventWriter<CubeRecieved>,
+fn cube_spawner(
+    mut query: Query<(Entity, &mut CubeSpawner, &GlobalTransform)>,
+    level: Res<Level>,
+    mut slots: ResMut<CubeSlots>,
     mut cmds: Commands,
-    asset_server: Res<AssetServer>,
 ) {
-    for (entity, parent, cube) in query.iter() {
-        if let Ok(mut proc) = processors.get_mut(parent.get()) {
-            if cube.0 == proc.color {
-                proc.count += 1;
-                event.send(CubeRecieved);
-            } else {
-                cmds.spawn(AudioBundle {
-                    source: asset_server.load("sounds/boop.ogg"),
-                    settings: PlaybackSettings {
-                        mode: bevy::audio::PlaybackMode::Despawn,
-                        volume: bevy::audio::Volume::new_relative(0.5),
-                        speed: fastrand::f32() * 0.3 + 0.8,
-                        paused: false,
+    for (entity, mut spawner, global) in query.iter_mut() {
+        if spawner.settings.count.is_some_and(|c| spawner.spawned >= c) {
+            continue;
+        }
+        spawner.cooldown -= TICK;
+        if spawner.cooldown > 0.0 {
+            continue;
+        }
+        // Stall while the previous cube is still in the way
+        let tile = level.to_discrete(global.transform_point(spawner.pos));
+        if slots.0.contains_key(&tile) {
+            continue;
+        }
+        let colors = &spawner.settings.colors;
+        let Some(&color) = colors.get(spawner.spawned % colors.len().max(1)) else {
+            continue;
+        };
+        cmds.entity(entity).with_children(|p| {
+            let cube = p
+                .spawn((
+                    SpatialBundle::from_transform(Transform::from_translation(spawner.pos)),
+                    Cube {
+                        color,
+                        path: 0,
+                        point: 0,
+                        progress: 1.0,
+                        previous: spawner.pos,
+                        position: spawner.pos,
                     },
-                });
+                    LevelEntity,
+                ))
+                .id();
+            slots.0.insert(tile, cube);
+        });
+        spawner.cooldown = spawner.settings.delay;
+        spawner.spawned += 1;
+    }
+}
+
+/// Advance the cube simulation by a number of ticks at once, without rendering in between.
+pub fn fast_forward(world: &mut World, ticks: usize) {
+    for _ in 0..ticks {
+        world.run_schedule(FixedUpdate);
+    }
+}
+
+fn simulation_running(simulation: Res<Simulation>) -> bool {
+    !simulation.paused || simulation.stepping
+}
+
+fn tick_clock(mut simulation: ResMut<Simulation>) {
+    simulation.elapsed += TICK;
+}
+
+/// Every level starts running at normal speed, whatever the last one was left at.
+pub fn reset_simulation(mut simulation: ResMut<Simulation>, mut fixed_time: ResMut<FixedTime>) {
+    *simulation = Simulation::default();
+    fixed_time.period = Duration::from_secs_f32(TICK);
+}
+
+fn simulation_keys(keys: Res<Input<KeyCode>>, mut event: EventWriter<SimulationEvent>) {
+    if keys.just_pressed(KeyCode::Space) {
+        event.send(SimulationEvent::TogglePause);
+    }
+    for (key, speed) in [(KeyCode::Key1, 1), (KeyCode::Key2, 2), (KeyCode::Key4, 4)] {
+        if keys.just_pressed(key) {
+            event.send(SimulationEvent::Speed(speed));
+        }
+    }
+    if keys.just_pressed(KeyCode::Period) {
+        event.send(SimulationEvent::Step);
+    }
+}
+
+fn apply_simulation(
+    mut event: EventReader<SimulationEvent>,
+    mut simulation: ResMut<Simulation>,
+    mut fixed_time: ResMut<FixedTime>,
+) {
+    for ev in event.iter() {
+        match ev {
+            SimulationEvent::TogglePause => simulation.paused = !simulation.paused,
+            SimulationEvent::Speed(speed) => {
+                simulation.paused = false;
+                simulation.speed = *speed;
+                // Run more ticks per second, rather than longer ticks, so the simulation stays the
+                // same at any speed
+                fixed_time.period = Duration::from_secs_f32(TICK / *speed as f32);
+            }
+            SimulationEvent::Step => {
+                simulation.paused = true;
+                simulation.steps += 1;
             }
-            cmds.entity(entity).despawn_recursive();
-        } else {
-            cmds.entity(entity).remove_parent_in_place();
         }
     }
 }
 
-fn cube_spawner(
-    mut query: Query<(Entity, &mut CubeSpawner)>,
-    time: Res<Time>,
+fn step_simulation(world: &mut World) {
+    let steps = std::mem::take(&mut world.resource_mut::<Simulation>().steps);
+    if steps == 0 {
+        return;
+    }
+    world.resource_mut::<Simulation>().stepping = true;
+    fast_forward(world, steps * (SEGMENT_TIME / TICK).round() as usize);
+    world.resource_mut::<Simulation>().stepping = false;
+}
+
+/// Place the cubes between their last two simulated positions, so they move smoothly no matter
+/// the frame rate.
+fn interpolate_cubes(
+    mut cubes: Query<(&Cube, &mut Transform)>,
+    fixed_time: Res<FixedTime>,
+    simulation: Res<Simulation>,
+) {
+    // Time keeps accumulating while paused, without any ticks to use it
+    let alpha = if simulation.paused {
+        1.0
+    } else {
+        fixed_time.accumulated().as_secs_f32() / fixed_time.period.as_secs_f32()
+    };
+    for (cube, mut transform) in cubes.iter_mut() {
+        transform.translation = cube.previous.lerp(cube.position, alpha.min(1.0));
+    }
+}
+
+fn cube_scenes(
+    cubes: Query<(Entity, &Cube, Option<&Handle<Scene>>, Option<&Tint>), Changed<Cube>>,
     mut cmds: Commands,
     asset_server: Res<AssetServer>,
+    palette: Res<Palette>,
 ) {
-    for (entity, mut spawner) in query.iter_mut() {
-        if spawner.next < time.elapsed_seconds() {
-            cmds.entity(entity).with_children(|p| {
-                p.spawn((
-                    SceneBundle {
-                        scene: asset_server.load(spawner.color.cube_path()),
-                        transform: Transform::from_translation(spawner.pos),
-                        ..Default::default()
-                    },
-                    Cube(spawner.color),
-                    LevelEntity,
-                ));
-            });
-            spawner.next = time.elapsed_seconds() + spawner.delay;
+    for (entity, cube, scene, tint) in cubes.iter() {
+        let (handle, new_tint) = palette.cube_model(cube.color, &asset_server);
+        // A repainted cube may keep the same scene with a different tint, which respawns the scene
+        if scene != Some(&handle) || tint != Some(&new_tint) {
+            cmds.entity(entity).insert((handle, new_tint));
         }
     }
 }
 
+fn reject_sound(
+    mut rejected: EventReader<CubeRejected>,
+    mut cmds: Commands,
+    asset_server: Res<AssetServer>,
+) {
+    for _ in rejected.iter() {
+        cmds.spawn(AudioBundle {
+            source: asset_server.load("sounds/boop.ogg"),
+            settings: PlaybackSettings {
+                mode: bevy::audio::PlaybackMode::Despawn,
+                volume: bevy::audio::Volume::new_relative(0.5),
+                speed: fastrand::f32() * 0.3 + 0.8,
+                paused: false,
+            },
+        });
+    }
+}
+
 fn check_connection(
     event: EventReader<CubeRecieved>,
-    routers: Query<(Entity, &CubeRouter, &GlobalTransform)>,
-    spawners: Query<&CubeSpawner>,
-    processors: Query<(&CubeRouter, &GlobalTransform, &CubeProcessor)>,
+    processors: Query<&CubeProcessor>,
+    routing: Res<Routing>,
+    objectives: Res<Objectives>,
     mut victory: ResMut<ShowVictory>,
 ) {
     if event.is_empty() {
         return;
     }
-    for (_, _, processor) in processors.iter() {
+    for processor in processors.iter() {
         if processor.count == 0 {
             return;
         }
     }
-    for (router, global, processor) in processors.iter() {
-        let mut pos = global.transform_point(router.0[0]);
-        'outer: loop {
-            for (rent, router, rtrans) in routers.iter() {
-                let pos2 = rtrans.transform_point(*router.0.last().unwrap());
-                if pos.distance_squared(pos2) < 0.1 {
-                    if let Ok(spawner) = spawners.get(rent) {
-                        if spawner.color == processor.color {
-                            break 'outer;
-                        }
-                    }
-                    pos = rtrans.transform_point(router.0[0]);
-                    continue 'outer;
-                }
-            }
-            return;
+    if routing.is_solved() && objectives.is_complete() {
+        victory.show();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A level with an input at the bottom, feeding a straight belt up to an output.
+    fn app(delay: f32, output: CubeColor) -> App {
+        spawn_app(
+            SpawnSettings {
+                delay,
+                ..SpawnSettings::new(CubeColor::GREEN)
+            },
+            output,
+        )
+    }
+
+    fn spawn_app(settings: SpawnSettings, output: CubeColor) -> App {
+        let mut app = App::new();
+        app.add_state::<AppState>().add_plugins(CubePlugin);
+        app.world.insert_resource(State::new(AppState::Level));
+        let level = Level::new(3, 5);
+        let mut router = |dis: Dis2, path: Vec<Vec3>| {
+            let transform = Transform::from_translation(level.to_vec3(dis));
+            app.world
+                .spawn((
+                    transform,
+                    GlobalTransform::from(transform),
+                    CubeRouter::new(path.into_iter().map(|p| p + Vec3::Y).collect()),
+                ))
+                .id()
+        };
+        let input = router(Dis2::new(1, 4), vec![Vec3::ZERO, Vec3::NEG_Z * 0.5]);
+        let belt = (0..7).map(|i| Vec3::Z * (0.5 - i as f32 * 0.5)).collect();
+        router(Dis2::new(1, 3), belt);
+        let processor = router(Dis2::new(1, 0), vec![Vec3::Z * 0.5, Vec3::ZERO]);
+        app.world
+            .entity_mut(input)
+            .insert(CubeSpawner::new(Vec3::Y, settings));
+        app.world
+            .entity_mut(processor)
+            .insert(CubeProcessor::new(output));
+        app.world.insert_resource(level);
+        app
+    }
+
+    fn delivered(app: &mut App) -> usize {
+        app.world
+            .query::<&CubeProcessor>()
+            .iter(&app.world)
+            .map(|p| p.count())
+            .sum()
+    }
+
+    #[test]
+    fn test_simulation() {
+        let mut a = app(2.0, CubeColor::GREEN);
+        let mut b = app(2.0, CubeColor::GREEN);
+        fast_forward(&mut a.world, 60 * 21);
+        fast_forward(&mut b.world, 60 * 21);
+        // A cube takes six seconds to arrive, and one is spawned every two seconds
+        assert_eq!(delivered(&mut a), 8);
+        assert_eq!(delivered(&mut b), 8);
+
+        let mut wrong = app(2.0, CubeColor::PURPLE);
+        fast_forward(&mut wrong.world, 60 * 20);
+        assert_eq!(delivered(&mut wrong), 0);
+    }
+
+    #[test]
+    fn test_spawn_settings() {
+        let settings = SpawnSettings {
+            offset: 4.0,
+            count: Some(3),
+            colors: vec![CubeColor::GREEN, CubeColor::PURPLE],
+            ..SpawnSettings::new(CubeColor::GREEN)
+        };
+        let mut app = spawn_app(settings, CubeColor::GREEN);
+        // The first cube is spawned four seconds late, and takes six seconds to arrive
+        fast_forward(&mut app.world, 60 * 9);
+        assert_eq!(delivered(&mut app), 0);
+        fast_forward(&mut app.world, 60 * 30);
+        // Green, purple and green again, and then the input stops
+        assert_eq!(delivered(&mut app), 2);
+        assert_eq!(app.world.query::<&Cube>().iter(&app.world).count(), 0);
+    }
+
+    #[test]
+    fn test_pause() {
+        let mut app = app(2.0, CubeColor::GREEN);
+        app.world.resource_mut::<Simulation>().paused = true;
+        fast_forward(&mut app.world, 60 * 20);
+        assert_eq!(delivered(&mut app), 0);
+        assert_eq!(app.world.resource::<Simulation>().elapsed(), 0.0);
+
+        // Stepping runs the simulation even while paused
+        app.world.resource_mut::<Simulation>().steps = 20;
+        step_simulation(&mut app.world);
+        assert!(delivered(&mut app) > 0);
+        assert!(app.world.resource::<Simulation>().paused);
+    }
+
+    #[test]
+    fn test_congestion() {
+        let mut app = app(0.1, CubeColor::GREEN);
+        for _ in 0..60 * 20 {
+            fast_forward(&mut app.world, 1);
+            let level = app.world.resource::<Level>().clone();
+            let mut tiles = app
+                .world
+                .query::<(&Cube, &Parent)>()
+                .iter(&app.world)
+                .map(|(cube, parent)| {
+                    let router = app.world.get::<CubeRouter>(parent.get()).unwrap();
+                    let global = app.world.get::<GlobalTransform>(parent.get()).unwrap();
+                    cube_tile(&router.paths[cube.path], cube.point, global, &level)
+                })
+                .collect::<Vec<_>>();
+            let count = tiles.len();
+            tiles.sort();
+            tiles.dedup();
+            assert_eq!(tiles.len(), count, "Two cubes share a tile");
         }
+        // Each cube holds its tile for 1.5 seconds, however quickly the cubes are spawned
+        assert!(delivered(&mut app) <= 10);
     }
-    victory.show();
 }
@@ -0,0 +1,787 @@
+#![allow(clippy::type_complexity)]
+
+use std::collections::BTreeMap;
+
+use bevy::prelude::*;
+use bevy::window::PrimaryWindow;
+
+use crate::cubes::{CubeColor, SpawnSettings};
+use crate::game::{Dis2, KickTable, Level};
+use crate::levels::{LevelEntity, LevelState};
+use crate::load::{level_parse, level_spawn, level_surround, LevelFile, Object};
+use crate::objectives::Objective;
+use crate::palette::Palette;
+use crate::ui::{spawn_button, LeaveLevelButton, ShowDialog, PANEL_COLOR};
+use crate::AppState;
+
+pub struct EditorPlugin;
+
+impl Plugin for EditorPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<EditorPath>()
+            .add_systems(OnEnter(AppState::Editor), setup_editor)
+            .add_systems(OnExit(AppState::Editor), reset_editor_path)
+            .add_systems(
+                Update,
+                (
+                    brush_button,
+                    save_button,
+                    type_brush,
+                    paint,
+                    (rebuild_level, update_status).run_if(resource_exists_and_changed::<Editor>()),
+                )
+                    .chain()
+                    .run_if(in_state(AppState::Editor)),
+            );
+        // Editing the campaign is for working on the game, not for players
+        #[cfg(debug_assertions)]
+        app.add_systems(
+            Update,
+            open_in_editor
+                .run_if(in_state(AppState::Level).and_then(in_state(LevelState::Campaign))),
+        );
+    }
+}
+
+const EDITOR_PATH: &str = "levels/editor.ron";
+/// Where edited levels are saved, so the levels shipped in `assets/` are never overwritten.
+#[cfg(not(target_arch = "wasm32"))]
+const USER_LEVELS: &str = "conveyor_chaos_levels";
+
+/// The level being edited, as an asset path: the campaign level the editor was opened from, or a
+/// scratch level when opened from the main menu.
+#[derive(Resource)]
+pub struct EditorPath(pub String);
+
+impl Default for EditorPath {
+    fn default() -> Self {
+        Self(EDITOR_PATH.to_string())
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl EditorPath {
+    /// The user's copy of the level, which is saved to and loaded in preference to the asset.
+    fn user_copy(&self) -> std::path::PathBuf {
+        let name = std::path::Path::new(&self.0)
+            .file_name()
+            .unwrap_or_default();
+        std::path::Path::new(USER_LEVELS).join(name)
+    }
+
+    fn read(&self) -> Option<LevelFile> {
+        let asset = std::path::Path::new("assets").join(&self.0);
+        [self.user_copy(), asset]
+            .iter()
+            .find_map(|p| std::fs::read_to_string(p).ok())
+            .and_then(|s| ron::from_str(&s).ok())
+    }
+
+    fn write(&self, file: &LevelFile) -> Result<std::path::PathBuf, String> {
+        let path = self.user_copy();
+        let s = ron::ser::to_string_pretty(file, ron::ser::PrettyConfig::default())
+            .map_err(|e| e.to_string())?;
+        std::fs::create_dir_all(USER_LEVELS).map_err(|e| e.to_string())?;
+        std::fs::write(&path, s).map_err(|e| e.to_string())?;
+        Ok(path)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Brush {
+    Tile(char),
+    Belt,
+    Splitter,
+    Merger,
+    Painter,
+    Crate,
+}
+
+const TILE_BRUSHES: [(&str, Brush); 4] = [
+    ("Floor", Brush::Tile(' ')),
+    ("Wall", Brush::Tile('#')),
+    ("Empty", Brush::Tile('E')),
+    ("Loading bay", Brush::Tile('L')),
+];
+
+const OBJECT_BRUSHES: [(&str, Brush); 5] = [
+    ("Belt", Brush::Belt),
+    ("Splitter", Brush::Splitter),
+    ("Merger", Brush::Merger),
+    ("Painter", Brush::Painter),
+    ("Crate", Brush::Crate),
+];
+
+/// All brushes, with an input and an output brush for each color in the palette.
+fn brushes(palette: &Palette) -> Vec<(String, Brush)> {
+    let colors = palette.colors.iter().flat_map(|c| {
+        [
+            (format!("{} in", c.name), Brush::Tile(c.input)),
+            (format!("{} out", c.name), Brush::Tile(c.output)),
+        ]
+    });
+    TILE_BRUSHES
+        .iter()
+        .map(|(name, brush)| (name.to_string(), *brush))
+        .chain(colors)
+        .chain(
+            OBJECT_BRUSHES
+                .iter()
+                .map(|(name, brush)| (name.to_string(), *brush)),
+        )
+        .collect()
+}
+
+#[derive(Resource, Clone)]
+pub struct Editor {
+    layout: Vec<Vec<char>>,
+    objects: Vec<Object>,
+    par: Option<usize>,
+    objectives: Vec<Objective>,
+    inputs: BTreeMap<(usize, usize), SpawnSettings>,
+    outputs: BTreeMap<(usize, usize), CubeColor>,
+    kicks: Option<KickTable>,
+    brush: Brush,
+    belt: String,
+    painter: (CubeColor, CubeColor),
+}
+
+impl Default for Editor {
+    fn default() -> Self {
+        let (width, height) = (12, 9);
+        let layout = (0..height)
+            .map(|j| {
+                (0..width)
+                    .map(|i| {
+                        if i == 0 || j == 0 || i == width - 1 || j == height - 1 {
+                            '#'
+                        } else {
+                            ' '
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+        Self {
+            layout,
+            objects: vec![],
+            par: None,
+            objectives: vec![],
+            inputs: BTreeMap::new(),
+            outputs: BTreeMap::new(),
+            kicks: None,
+            brush: Brush::Tile('#'),
+            belt: "f".to_string(),
+            painter: (CubeColor::GREEN, CubeColor::PURPLE),
+        }
+    }
+}
+
+impl Editor {
+    pub fn from_file(file: &LevelFile) -> Self {
+        Self {
+            layout: file
+                .layout
+                .iter()
+                .map(|row| row.chars().collect())
+                .collect(),
+            objects: file.objects.clone(),
+            par: file.par,
+            objectives: file.objectives.clone(),
+            inputs: file.inputs.clone(),
+            outputs: file.outputs.clone(),
+            kicks: file.kicks.clone(),
+            ..default()
+        }
+    }
+
+    pub fn to_file(&self, palette: &Palette) -> LevelFile {
+        LevelFile {
+            layout: self.layout.iter().map(|row| row.iter().collect()).collect(),
+            objects: self.objects.clone(),
+            par: self.par,
+            objectives: self.objectives.clone(),
+            // Drop the settings of inputs and outputs that have since been painted over
+            inputs: self
+                .inputs
+                .iter()
+                .filter(|(pos, _)| self.tile_is(**pos, |c| palette.input(c).is_some()))
+                .map(|(pos, s)| (*pos, s.clone()))
+                .collect(),
+            outputs: self
+                .outputs
+                .iter()
+                .filter(|(pos, _)| self.tile_is(**pos, |c| palette.output(c).is_some()))
+                .map(|(pos, c)| (*pos, *c))
+                .collect(),
+            kicks: self.kicks.clone(),
+        }
+    }
+
+    fn tile_is(&self, (column, row): (usize, usize), is: impl Fn(char) -> bool) -> bool {
+        self.layout
+            .get(row)
+            .and_then(|r| r.get(column))
+            .is_some_and(|c| is(*c))
+    }
+
+    /// Keep the input and output settings on their tiles when columns or rows are added or
+    /// removed at the start of the layout.
+    fn shift_settings(&mut self, dx: isize, dz: isize) {
+        let shift =
+            |(x, z): (usize, usize)| Some((x.checked_add_signed(dx)?, z.checked_add_signed(dz)?));
+        self.inputs = std::mem::take(&mut self.inputs)
+            .into_iter()
+            .filter_map(|(pos, s)| Some((shift(pos)?, s)))
+            .collect();
+        self.outputs = std::mem::take(&mut self.outputs)
+            .into_iter()
+            .filter_map(|(pos, c)| Some((shift(pos)?, c)))
+            .collect();
+    }
+
+    fn width(&self) -> usize {
+        self.layout
+            .iter()
+            .map(|row| row.len())
+            .max()
+            .unwrap_or_default()
+    }
+
+    /// Set a tile, growing the layout if the tile is just outside it.
+    pub fn set(&mut self, pos: Dis2, tile: char) {
+        let (mut x, mut z): (isize, isize) = pos.into();
+        let width = self.width() as isize;
+        let height = self.layout.len() as isize;
+        if x < -1 || z < -1 || x > width || z > height {
+            return;
+        }
+        if x == -1 {
+            self.layout.iter_mut().for_each(|row| row.insert(0, 'E'));
+            self.shift_settings(1, 0);
+            x = 0;
+        }
+        if z == -1 {
+            self.layout.insert(0, vec!['E'; width as usize]);
+            self.shift_settings(0, 1);
+            z = 0;
+        }
+        if z as usize == self.layout.len() {
+            self.layout.push(vec![]);
+        }
+        let row = &mut self.layout[z as usize];
+        if row.len() <= x as usize {
+            row.resize(x as usize + 1, 'E');
+        }
+        row[x as usize] = tile;
+        self.trim();
+    }
+
+    /// Remove empty rows and columns around the layout.
+    fn trim(&mut self) {
+        let empty = |row: &Vec<char>| row.iter().all(|c| *c == 'E');
+        while self.layout.last().is_some_and(empty) {
+            self.layout.pop();
+        }
+        while self.layout.first().is_some_and(empty) {
+            self.layout.remove(0);
+            self.shift_settings(0, -1);
+        }
+        while !self.layout.is_empty() && self.layout.iter().all(|r| r.first() == Some(&'E')) {
+            self.layout.iter_mut().for_each(|row| {
+                row.remove(0);
+            });
+            self.shift_settings(-1, 0);
+        }
+        for row in self.layout.iter_mut() {
+            while row.last() == Some(&'E') {
+                row.pop();
+            }
+        }
+        let width = self.width();
+        self.layout
+            .iter_mut()
+            .for_each(|row| row.resize(width, 'E'));
+    }
+
+    /// Get the layout character for an object, adding it to the objects if necessary.
+    fn object_tile(&mut self, object: Object) -> Option<char> {
+        let index = match self.objects.iter().position(|o| *o == object) {
+            Some(i) => i,
+            None if self.objects.len() < 10 => {
+                self.objects.push(object);
+                self.objects.len() - 1
+            }
+            None => return None,
+        };
+        char::from_digit(index as u32, 10)
+    }
+
+    /// Remove objects that are no longer used in the layout, renumbering the rest.
+    fn prune_objects(&mut self) {
+        let mut index = 0;
+        let mut mapping = [None; 10];
+        for (i, m) in mapping.iter_mut().enumerate().take(self.objects.len()) {
+            let c = char::from_digit(i as u32, 10).unwrap();
+            if self.layout.iter().any(|row| row.contains(&c)) {
+                *m = char::from_digit(index, 10);
+                index += 1;
+            }
+        }
+        let mut i = 0;
+        self.objects.retain(|_| {
+            i += 1;
+            mapping[i - 1].is_some()
+        });
+        for c in self.layout.iter_mut().flat_map(|row| row.iter_mut()) {
+            if let Some(Some(m)) = c.to_digit(10).map(|i| mapping[i as usize]) {
+                *c = m;
+            }
+        }
+    }
+}
+
+#[derive(Component)]
+struct BrushButton(Brush);
+
+#[derive(Component)]
+struct SaveButton;
+
+#[derive(Component)]
+struct StatusText;
+
+fn setup_editor(
+    mut cmds: Commands,
+    asset_server: Res<AssetServer>,
+    palette: Res<Palette>,
+    path: Res<EditorPath>,
+) {
+    #[cfg(not(target_arch = "wasm32"))]
+    let editor = path
+        .read()
+        .map_or_else(Editor::default, |f| Editor::from_file(&f));
+    #[cfg(target_arch = "wasm32")]
+    let editor = {
+        let _ = &path;
+        Editor::default()
+    };
+    cmds.insert_resource(editor);
+
+    cmds.spawn((
+        LevelEntity,
+        NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                top: Val::Px(10.0),
+                left: Val::Px(10.0),
+                flex_direction: FlexDirection::Column,
+                row_gap: Val::Px(4.0),
+                padding: UiRect::all(Val::Px(10.0)),
+                ..default()
+            },
+            background_color: PANEL_COLOR.into(),
+            ..default()
+        },
+    ))
+    .with_children(|parent| {
+        parent.spawn((
+            StatusText,
+            TextBundle::from_section(
+                "",
+                TextStyle {
+                    font: asset_server.load("fonts/Tourney-Medium.ttf"),
+                    font_size: 24.0,
+                    color: Color::BLACK,
+                },
+            ),
+        ));
+        for (text, brush) in brushes(&palette) {
+            spawn_button(parent, &text, 200.0, &asset_server).insert(BrushButton(brush));
+        }
+        spawn_button(parent, "Save", 200.0, &asset_server).insert(SaveButton);
+        spawn_button(parent, "Exit", 200.0, &asset_server)
+            .insert(LeaveLevelButton(LevelState::MainMenu));
+    });
+}
+
+fn brush_button(
+    interaction_query: Query<(&Interaction, &BrushButton), Changed<Interaction>>,
+    mut editor: ResMut<Editor>,
+) {
+    for (interaction, button) in interaction_query.iter() {
+        if matches!(interaction, Interaction::Pressed) {
+            editor.brush = button.0;
+        }
+    }
+}
+
+fn save_button(
+    interaction_query: Query<&Interaction, (Changed<Interaction>, With<SaveButton>)>,
+    editor: Res<Editor>,
+    palette: Res<Palette>,
+    path: Res<EditorPath>,
+    mut dialog: EventWriter<ShowDialog>,
+) {
+    for interaction in interaction_query.iter() {
+        if matches!(interaction, Interaction::Pressed) {
+            #[cfg(not(target_arch = "wasm32"))]
+            {
+                match path.write(&editor.to_file(&palette)) {
+                    Ok(saved) => dialog.send(ShowDialog(format!("Saved to '{}'", saved.display()))),
+                    Err(e) => dialog.send(ShowDialog(format!("Could not save level:\n{}", e))),
+                }
+            }
+            #[cfg(target_arch = "wasm32")]
+            {
+                let _ = (&editor, &palette, &path);
+                dialog.send(ShowDialog(
+                    "Saving is not supported in the browser".to_string(),
+                ));
+            }
+        }
+    }
+}
+
+/// Open the current campaign level in the editor, to save an edited copy of it.
+#[cfg(debug_assertions)]
+fn open_in_editor(
+    keys: Res<Input<KeyCode>>,
+    campaign: Res<crate::levels::Campaign>,
+    campaigns: Res<Assets<crate::levels::CampaignFile>>,
+    mut path: ResMut<EditorPath>,
+    mut leave: EventWriter<crate::levels::LeaveLevel>,
+    mut state: ResMut<NextState<AppState>>,
+) {
+    if !keys.just_pressed(KeyCode::F2) {
+        return;
+    }
+    if let Some(level) = campaign.current(&campaigns) {
+        path.0 = level.path.clone();
+        leave.send(crate::levels::LeaveLevel(LevelState::Editor));
+        state.set(AppState::Unloading);
+    }
+}
+
+fn reset_editor_path(mut path: ResMut<EditorPath>) {
+    *path = EditorPath::default();
+}
+
+/// The key that selects a color for the painter brush.
+fn initial(color: CubeColor) -> char {
+    color
+        .name()
+        .chars()
+        .next()
+        .unwrap_or_default()
+        .to_ascii_lowercase()
+}
+
+fn type_brush(
+    mut chars: EventReader<ReceivedCharacter>,
+    keys: Res<Input<KeyCode>>,
+    mut editor: ResMut<Editor>,
+    palette: Res<Palette>,
+) {
+    match editor.brush {
+        Brush::Belt => {
+            for ev in chars.iter() {
+                if matches!(ev.char, 'f' | 'F' | 'l' | 'L' | 'r' | 'R') {
+                    editor.belt.push(ev.char.to_ascii_lowercase());
+                }
+            }
+            if keys.just_pressed(KeyCode::Back) && editor.belt.len() > 1 {
+                editor.belt.pop();
+            }
+        }
+        Brush::Painter => {
+            for ev in chars.iter() {
+                let Some(color) = palette
+                    .colors
+                    .iter()
+                    .find(|c| initial(c.name) == ev.char.to_ascii_lowercase())
+                else {
+                    continue;
+                };
+                editor.painter = (editor.painter.1, color.name);
+            }
+        }
+        _ => chars.clear(),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn paint(
+    buttons: Res<Input<MouseButton>>,
+    interactions: Query<&Interaction>,
+    window: Query<&Window, With<PrimaryWindow>>,
+    camera: Query<(&Camera, &GlobalTransform)>,
+    level: Option<Res<Level>>,
+    mut editor: ResMut<Editor>,
+    mut cmds: Commands,
+    asset_server: Res<AssetServer>,
+    palette: Res<Palette>,
+) {
+    let erase = buttons.just_pressed(MouseButton::Right);
+    if !buttons.just_pressed(MouseButton::Left) && !erase {
+        return;
+    }
+    if interactions.iter().any(|i| *i != Interaction::None) {
+        return;
+    }
+    let (Some(level), Ok(window)) = (level, window.get_single()) else {
+        return;
+    };
+    let (camera, camera_transform) = camera.single();
+    let Some(ray) = window
+        .cursor_position()
+        .and_then(|p| camera.viewport_to_world(camera_transform, p))
+    else {
+        return;
+    };
+    let Some(dist) = ray.intersect_plane(Vec3::ZERO, Vec3::Y) else {
+        return;
+    };
+    let pos = level.to_discrete(ray.get_point(dist));
+
+    let mut edited = editor.clone();
+    let tile = match (erase, edited.brush) {
+        (true, _) => Some(' '),
+        (false, Brush::Tile(c)) => Some(c),
+        (false, Brush::Belt) => edited.object_tile(Object::Belt(edited.belt.clone())),
+        (false, Brush::Splitter) => edited.object_tile(Object::Splitter),
+        (false, Brush::Merger) => edited.object_tile(Object::Merger),
+        (false, Brush::Painter) => {
+            let (from, to) = edited.painter;
+            edited.object_tile(Object::Painter(from, to))
+        }
+        // Larger crates are made by editing the tiles in the level file
+        (false, Brush::Crate) => edited.object_tile(Object::Crate(vec![(0, 0)])),
+    };
+    let valid = match tile {
+        Some(tile) => {
+            edited.set(pos, tile);
+            edited.prune_objects();
+            !edited.layout.is_empty() && level_parse(&edited.to_file(&palette), &palette).is_ok()
+        }
+        None => false,
+    };
+    if valid {
+        editor.layout = edited.layout;
+        editor.objects = edited.objects;
+        editor.inputs = edited.inputs;
+        editor.outputs = edited.outputs;
+    } else {
+        cmds.spawn(AudioBundle {
+            source: asset_server.load("sounds/boop.ogg"),
+            settings: PlaybackSettings {
+                mode: bevy::audio::PlaybackMode::Despawn,
+                volume: bevy::audio::Volume::new_relative(0.5),
+                speed: fastrand::f32() * 0.2 + 0.9,
+                paused: false,
+            },
+        });
+    }
+}
+
+fn rebuild_level(
+    query: Query<
+        Entity,
+        (
+            With<LevelEntity>,
+            Without<Parent>,
+            Without<Node>,
+            Without<DirectionalLight>,
+        ),
+    >,
+    editor: Res<Editor>,
+    mut cmds: Commands,
+    asset_server: Res<AssetServer>,
+    palette: Res<Palette>,
+) {
+    for entity in query.iter() {
+        cmds.entity(entity).despawn_recursive();
+    }
+    if let Ok(mut layout) = level_parse(&editor.to_file(&palette), &palette) {
+        level_surround(&mut layout);
+        level_spawn(layout, editor.kicks.clone(), cmds, asset_server, &palette);
+    }
+}
+
+fn update_status(
+    editor: Res<Editor>,
+    palette: Res<Palette>,
+    mut query: Query<&mut Text, With<StatusText>>,
+) {
+    let brush = brushes(&palette)
+        .into_iter()
+        .find(|(_, b)| *b == editor.brush)
+        .map_or(String::new(), |(name, _)| name);
+    let text = match editor.brush {
+        Brush::Belt => format!("Brush: {} '{}'\n(type f/l/r)", brush, editor.belt),
+        Brush::Painter => format!(
+            "Brush: {} {} to {}\n(type {})",
+            brush,
+            editor.painter.0,
+            editor.painter.1,
+            palette
+                .colors
+                .iter()
+                .map(|c| initial(c.name).to_string())
+                .collect::<Vec<_>>()
+                .join("/")
+        ),
+        _ => format!("Brush: {}", brush),
+    };
+    for mut status in query.iter_mut() {
+        status.sections[0].value = text.clone();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn palette() -> Palette {
+        ron::from_str(&std::fs::read_to_string("assets/colors.palette.ron").unwrap()).unwrap()
+    }
+
+    /// A small level with settings on its input and output.
+    fn editor() -> Editor {
+        Editor {
+            layout: ["#o#", "# #", "#i#"]
+                .iter()
+                .map(|r| r.chars().collect())
+                .collect(),
+            inputs: [((1, 2), SpawnSettings::new(CubeColor::GREEN))].into(),
+            outputs: [((1, 0), CubeColor::GREEN)].into(),
+            ..default()
+        }
+    }
+
+    fn rows(editor: &Editor) -> Vec<String> {
+        editor.layout.iter().map(|r| r.iter().collect()).collect()
+    }
+
+    fn settings(editor: &Editor) -> ((usize, usize), (usize, usize)) {
+        (
+            *editor.inputs.keys().next().unwrap(),
+            *editor.outputs.keys().next().unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_grow() {
+        let mut e = editor();
+        e.set(Dis2::new(-1, 1), ' ');
+        assert_eq!(rows(&e), ["E#o#", " # #", "E#i#"]);
+        assert_eq!(settings(&e), ((2, 2), (2, 0)));
+
+        let mut e = editor();
+        e.set(Dis2::new(1, -1), ' ');
+        assert_eq!(rows(&e), ["E E", "#o#", "# #", "#i#"]);
+        assert_eq!(settings(&e), ((1, 3), (1, 1)));
+
+        let mut e = editor();
+        e.set(Dis2::new(3, 1), ' ');
+        assert_eq!(rows(&e), ["#o#E", "# # ", "#i#E"]);
+        assert_eq!(settings(&e), ((1, 2), (1, 0)));
+
+        let mut e = editor();
+        e.set(Dis2::new(1, 3), ' ');
+        assert_eq!(rows(&e), ["#o#", "# #", "#i#", "E E"]);
+        assert_eq!(settings(&e), ((1, 2), (1, 0)));
+
+        // Too far outside the layout
+        let mut e = editor();
+        e.set(Dis2::new(-2, 1), ' ');
+        e.set(Dis2::new(1, 4), ' ');
+        assert_eq!(rows(&e), rows(&editor()));
+    }
+
+    #[test]
+    fn test_trim() {
+        // Growing and then clearing the same tile gives back the original layout
+        let mut e = editor();
+        e.set(Dis2::new(-1, 1), ' ');
+        e.set(Dis2::new(0, 1), 'E');
+        assert_eq!(rows(&e), rows(&editor()));
+        assert_eq!(settings(&e), ((1, 2), (1, 0)));
+
+        let mut e = editor();
+        e.set(Dis2::new(1, -1), ' ');
+        e.set(Dis2::new(1, 0), 'E');
+        assert_eq!(rows(&e), rows(&editor()));
+        assert_eq!(settings(&e), ((1, 2), (1, 0)));
+
+        let mut e = editor();
+        for z in 0..3 {
+            e.set(Dis2::new(2, z), 'E');
+        }
+        assert_eq!(rows(&e), ["#o", "# ", "#i"]);
+        assert_eq!(settings(&e), ((1, 2), (1, 0)));
+
+        let mut e = editor();
+        for x in 0..3 {
+            e.set(Dis2::new(x, 2), 'E');
+        }
+        assert_eq!(rows(&e), ["#o#", "# #"]);
+        assert!(e.inputs.contains_key(&(1, 2)));
+
+        // Removing the first row drops the output on it and moves the input up
+        let mut e = editor();
+        for x in 0..3 {
+            e.set(Dis2::new(x, 0), 'E');
+        }
+        assert_eq!(rows(&e), ["# #", "#i#"]);
+        assert_eq!(e.inputs.keys().collect::<Vec<_>>(), [&(1, 1)]);
+        assert!(e.outputs.is_empty());
+
+        // Removing the first column moves both
+        let mut e = editor();
+        for z in 0..3 {
+            e.set(Dis2::new(0, z), 'E');
+        }
+        assert_eq!(rows(&e), ["o#", " #", "i#"]);
+        assert_eq!(settings(&e), ((0, 2), (0, 0)));
+    }
+
+    #[test]
+    fn test_prune_objects() {
+        let belt = |path: &str| Object::Belt(path.to_string());
+        let mut e = editor();
+        e.objects = vec![belt("f"), belt("ff"), belt("fff")];
+        e.set(Dis2::new(1, 1), '2');
+        e.prune_objects();
+        assert_eq!(e.objects, [belt("fff")]);
+        assert_eq!(rows(&e), ["#o#", "#0#", "#i#"]);
+
+        // Objects keep their numbers while they are still used
+        let tile = e.object_tile(belt("ff")).unwrap();
+        e.set(Dis2::new(-1, 1), tile);
+        e.prune_objects();
+        assert_eq!(e.objects, [belt("fff"), belt("ff")]);
+        assert_eq!(rows(&e), ["E#o#", "1#0#", "E#i#"]);
+
+        e.set(Dis2::new(2, 1), ' ');
+        e.prune_objects();
+        assert_eq!(e.objects, [belt("ff")]);
+        assert_eq!(rows(&e), ["E#o#", "0# #", "E#i#"]);
+    }
+
+    #[test]
+    fn test_palette_settings() {
+        // Red and cyan inputs and outputs only exist in the palette file
+        let file: LevelFile = ron::from_str(
+            "(
+                layout: [\"#V#v#\", \"#   #\", \"#A#a#\"],
+                objects: [],
+                inputs: {(1, 2): (count: Some(3)), (3, 2): (colors: [Cyan, Red])},
+                outputs: {(1, 0): Cyan},
+            )",
+        )
+        .unwrap();
+        let saved = Editor::from_file(&file).to_file(&palette());
+        assert_eq!(saved.inputs, file.inputs);
+        assert_eq!(saved.outputs, file.outputs);
+    }
+}
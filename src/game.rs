@@ -1,22 +1,45 @@
+use std::collections::{HashMap, VecDeque};
 use std::f32::consts::PI;
-use std::ops::{Add, AddAssign};
+use std::ops::{Add, AddAssign, Sub};
 
+use bevy::input::mouse::{MouseScrollUnit, MouseWheel};
 use bevy::math::vec4;
 use bevy::prelude::*;
 use bevy_mod_picking::prelude::*;
+use serde::{Deserialize, Serialize};
 
+use crate::cubes::CubeColor;
+use crate::objects::Edge;
+use crate::score::Score;
 use crate::AppState;
 
 pub struct GamePlugin;
 
 impl Plugin for GamePlugin {
     fn build(&self, app: &mut App) {
-        app
+        app.add_event::<HistoryEvent>()
+            .add_event::<MoveBlock>()
+            .add_event::<RotateBlock>()
+            .init_resource::<History>()
             // .add_systems(Startup, setup)
             .add_systems(
                 Update,
-                make_scene_draggable.run_if(in_state(AppState::Level)),
-            );
+                (
+                    make_scene_draggable,
+                    history_keys,
+                    apply_history,
+                    animate_slides,
+                    wheel_rotate,
+                    begin_gestures.before(move_blocks),
+                    move_blocks,
+                    end_gestures.after(move_blocks),
+                    rotate_blocks,
+                    draw_locks.run_if(resource_exists::<Level>()),
+                    draw_rotation_ghost.run_if(resource_exists::<Level>()),
+                )
+                    .run_if(in_state(AppState::Level)),
+            )
+            .add_systems(OnEnter(AppState::Unloading), reset_history);
     }
 }
 
@@ -27,7 +50,9 @@ impl Plugin for GamePlugin {
 // ) {
 // }
 
-#[derive(Debug, PartialEq, PartialOrd, Ord, Eq, Clone, Copy, Default)]
+#[derive(
+    Debug, PartialEq, PartialOrd, Ord, Eq, Clone, Copy, Default, Hash, Serialize, Deserialize,
+)]
 pub struct Dis2 {
     x: isize,
     z: isize,
@@ -73,6 +98,11 @@ impl From<(usize, usize)> for Dis2 {
         Self::new(value.0 as isize, value.1 as isize)
     }
 }
+impl From<Dis2> for (isize, isize) {
+    fn from(value: Dis2) -> Self {
+        (value.x, value.z)
+    }
+}
 
 impl AddAssign for Dis2 {
     fn add_assign(&mut self, rhs: Self) {
@@ -89,12 +119,23 @@ impl Add for Dis2 {
     }
 }
 
-#[derive(Resource)]
+impl Sub for Dis2 {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        Self::new(self.x - rhs.x, self.z - rhs.z)
+    }
+}
+
+#[derive(Resource, Clone)]
 pub struct Level {
     width: usize,
     height: usize,
     index: usize,
     floor: Vec<usize>,
+    inputs: Vec<(Edge, CubeColor)>,
+    outputs: Vec<(Edge, CubeColor)>,
+    pub kicks: KickTable,
 }
 
 impl Level {
@@ -104,9 +145,30 @@ impl Level {
             height,
             index: 0,
             floor: vec![usize::MAX; width * height],
+            inputs: vec![],
+            outputs: vec![],
+            kicks: KickTable::default(),
         }
     }
 
+    pub fn add_input(&mut self, edge: Edge, color: CubeColor) {
+        self.inputs.push((edge, color));
+    }
+
+    pub fn add_output(&mut self, edge: Edge, color: CubeColor) {
+        self.outputs.push((edge, color));
+    }
+
+    /// The edges cubes leave the inputs through.
+    pub fn inputs(&self) -> &[(Edge, CubeColor)] {
+        &self.inputs
+    }
+
+    /// The edges cubes enter the outputs through.
+    pub fn outputs(&self) -> &[(Edge, CubeColor)] {
+        &self.outputs
+    }
+
     #[allow(dead_code)]
     pub fn get(&self, x: usize, z: usize) -> Option<&usize> {
         if x >= self.width {
@@ -163,6 +225,51 @@ impl Level {
         true
     }
 
+    /// Turn the block a quarter, moving it by the first offset in the kick table that makes room.
+    pub fn try_rotate(&self, block: &Block, turn: Turn) -> Option<(Dis2, Rotation)> {
+        let (rotation, kicks) = match turn {
+            Turn::Left => (block.rotation.left(), &self.kicks.left),
+            Turn::Right => (block.rotation.right(), &self.kicks.right),
+        };
+        kicks
+            .iter()
+            .map(|(x, z)| block.position + Dis2::new(*x, *z).rotated(block.rotation))
+            .find(|pos| self.try_place(block, *pos, rotation))
+            .map(|pos| (pos, rotation))
+    }
+
+    /// The tiles a block passes on the shortest way to the free position nearest the target,
+    /// without rotating. Ties are broken by the order of the steps, so the same drag always takes
+    /// the same path.
+    pub fn drag_path(&self, block: &Block, target: Dis2) -> Vec<Dis2> {
+        let start = (block.position, block.rotation);
+        let mut previous = HashMap::from([(block.position, block.position)]);
+        let mut queue = VecDeque::from([block.position]);
+        let mut best = block.position;
+        while let Some(pos) = queue.pop_front() {
+            if pos.distance(target) < best.distance(target) {
+                best = pos;
+            }
+            for step in [Dis2::X, Dis2::NEG_X, Dis2::Z, Dis2::NEG_Z] {
+                let next = pos + step;
+                if !previous.contains_key(&next)
+                    && block.lock.allows(start, (next, block.rotation))
+                    && self.try_place(block, next, block.rotation)
+                {
+                    previous.insert(next, pos);
+                    queue.push_back(next);
+                }
+            }
+        }
+        let mut path = vec![];
+        while best != block.position {
+            path.push(best);
+            best = previous[&best];
+        }
+        path.reverse();
+        path
+    }
+
     pub fn remove(&mut self, block: &Block) {
         block.iter().for_each(|d| {
             let tile = self.getd_mut(d).unwrap();
@@ -231,7 +338,7 @@ impl std::fmt::Debug for Level {
     }
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Hash, Serialize, Deserialize)]
 pub enum Rotation {
     D0,
     D90,
@@ -299,10 +406,11 @@ impl Add for Rotation {
     }
 }
 
-#[derive(Component)]
+#[derive(Component, Clone)]
 pub struct Block {
     pub index: usize,
     pub tiles: Vec<Dis2>,
+    pub lock: Lock,
     rotation: Rotation,
     position: Dis2,
 }
@@ -312,6 +420,7 @@ impl Block {
         Self {
             index,
             tiles: vec![Dis2::ZERO],
+            lock: Lock::Free,
             rotation: Rotation::D0,
             position,
         }
@@ -335,6 +444,14 @@ impl Block {
         self
     }
 
+    pub fn position(&self) -> Dis2 {
+        self.position
+    }
+
+    pub fn rotation(&self) -> Rotation {
+        self.rotation
+    }
+
     pub fn translate(&mut self, position: Dis2) -> &mut Self {
         self.position = position;
         self
@@ -356,6 +473,223 @@ impl Block {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Turn {
+    Left,
+    Right,
+}
+
+/// The offsets tried in turn when a block rotates into something, like the wall kicks of falling
+/// block games. Offsets are relative to the block before it turns, so `(1, 0)` is a step to its
+/// right.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct KickTable {
+    pub left: Vec<(isize, isize)>,
+    pub right: Vec<(isize, isize)>,
+}
+
+impl Default for KickTable {
+    fn default() -> Self {
+        Self {
+            left: vec![(0, 0), (0, -1), (1, 0), (-1, 0), (0, 1)],
+            right: vec![(0, 0), (0, -1), (-1, 0), (1, 0), (0, 1)],
+        }
+    }
+}
+
+/// How the player may move a block, as set in the level file.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Lock {
+    #[default]
+    Free,
+    /// The block can not be moved or rotated.
+    Fixed,
+    /// The block can be moved, but not rotated.
+    NoRotation,
+    /// The block can only be moved along its row, without rotating.
+    SlideX,
+    /// The block can only be moved along its column, without rotating.
+    SlideZ,
+}
+
+impl Lock {
+    /// Check whether a block can be moved from one placement to another.
+    pub fn allows(&self, from: (Dis2, Rotation), to: (Dis2, Rotation)) -> bool {
+        let ((from, from_rotation), (to, to_rotation)) = (from, to);
+        match self {
+            Lock::Free => true,
+            Lock::Fixed => from == to && from_rotation == to_rotation,
+            Lock::NoRotation => from_rotation == to_rotation,
+            Lock::SlideX => from_rotation == to_rotation && from.z == to.z,
+            Lock::SlideZ => from_rotation == to_rotation && from.x == to.x,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Move {
+    entity: Entity,
+    from: (Dis2, Rotation),
+    to: (Dis2, Rotation),
+}
+
+#[derive(Resource, Default)]
+pub struct History {
+    undo: Vec<Move>,
+    redo: Vec<Move>,
+    /// The block being dragged, and the length of `undo` when the drag started.
+    gesture: Option<(Entity, usize)>,
+}
+
+impl History {
+    /// Add a move, combining it with the earlier moves of the same gesture into one undo step.
+    pub fn push(&mut self, action: Move) {
+        self.redo.clear();
+        let len = self.undo.len();
+        match (self.gesture, self.undo.last_mut()) {
+            (Some((entity, start)), Some(last)) if entity == action.entity && len == start + 1 => {
+                last.to = action.to;
+                // Dragging a block back to where it started leaves nothing to undo
+                if last.from == last.to {
+                    self.undo.pop();
+                }
+            }
+            _ => self.undo.push(action),
+        }
+    }
+
+    /// Combine the moves of a block into one undo step, until the gesture ends.
+    pub fn begin_gesture(&mut self, entity: Entity) {
+        self.gesture = Some((entity, self.undo.len()));
+    }
+
+    pub fn end_gesture(&mut self) {
+        self.gesture = None;
+    }
+}
+
+#[derive(Event, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HistoryEvent {
+    Undo,
+    Redo,
+}
+
+/// Move a block as close to the target as it can get, without rotating.
+#[derive(Event, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MoveBlock {
+    pub entity: Entity,
+    pub target: Dis2,
+}
+
+#[derive(Event, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RotateBlock {
+    pub entity: Entity,
+    pub turn: Turn,
+}
+
+fn set_block(
+    level: &mut Level,
+    block: &mut Block,
+    transform: &mut Transform,
+    (position, rotation): (Dis2, Rotation),
+) {
+    level.remove(block);
+    block.translate(position).rotate(rotation);
+    level.place(block);
+    transform.translation = level.to_vec3(position);
+    transform.rotation = Quat::from_rotation_y(rotation.as_radians());
+}
+
+/// Tiles per second that dragged blocks slide at.
+const SLIDE_SPEED: f32 = 15.0;
+
+/// Moves a block along the tiles of a drag, after it has already been placed at the end.
+#[derive(Component)]
+struct Slide {
+    points: Vec<Vec3>,
+    progress: f32,
+}
+
+fn animate_slides(
+    mut commands: Commands,
+    mut query: Query<(Entity, &mut Slide, &mut Transform)>,
+    time: Res<Time>,
+) {
+    for (entity, mut slide, mut transform) in query.iter_mut() {
+        slide.progress += time.delta_seconds() * SLIDE_SPEED;
+        let i = slide.progress as usize;
+        if let (Some(from), Some(to)) = (slide.points.get(i), slide.points.get(i + 1)) {
+            transform.translation = from.lerp(*to, slide.progress.fract());
+        } else {
+            transform.translation = *slide.points.last().unwrap();
+            commands.entity(entity).remove::<Slide>();
+        }
+    }
+}
+
+fn history_keys(keys: Res<Input<KeyCode>>, mut event: EventWriter<HistoryEvent>) {
+    if !keys.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]) {
+        return;
+    }
+    let shift = keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+    if keys.just_pressed(KeyCode::Z) {
+        event.send(if shift {
+            HistoryEvent::Redo
+        } else {
+            HistoryEvent::Undo
+        });
+    } else if keys.just_pressed(KeyCode::Y) {
+        event.send(HistoryEvent::Redo);
+    }
+}
+
+fn apply_history(
+    mut event: EventReader<HistoryEvent>,
+    mut history: ResMut<History>,
+    mut block_query: Query<(&mut Transform, &mut Block)>,
+    mut level: ResMut<Level>,
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+) {
+    for ev in event.iter() {
+        let History { undo, redo, .. } = &mut *history;
+        let (from, to) = match ev {
+            HistoryEvent::Undo => (undo, redo),
+            HistoryEvent::Redo => (redo, undo),
+        };
+        let Some(action) = from.pop() else {
+            continue;
+        };
+        let Ok((mut transform, mut block)) = block_query.get_mut(action.entity) else {
+            continue;
+        };
+        let target = match ev {
+            HistoryEvent::Undo => action.from,
+            HistoryEvent::Redo => action.to,
+        };
+        if !level.try_place(&block, target.0, target.1) {
+            from.push(action);
+            continue;
+        }
+        set_block(&mut level, &mut block, &mut transform, target);
+        commands.entity(action.entity).remove::<Slide>();
+        to.push(action);
+        commands.spawn(AudioBundle {
+            source: asset_server.load("sounds/click.ogg"),
+            settings: PlaybackSettings {
+                mode: bevy::audio::PlaybackMode::Despawn,
+                volume: bevy::audio::Volume::new_relative(0.5),
+                speed: fastrand::f32() * 0.3 + 0.75,
+                paused: false,
+            },
+        });
+    }
+}
+
+fn reset_history(mut history: ResMut<History>) {
+    *history = History::default();
+}
+
 #[derive(Component)]
 pub struct MakeSceneDraggable(pub Option<Entity>);
 
@@ -365,102 +699,189 @@ struct Draggable(Entity);
 fn on_drag(
     event: Listener<Pointer<Drag>>,
     root_query: Query<&Draggable>,
-    mut block_query: Query<(&mut Transform, &mut Block), With<Draggable>>,
+    block_query: Query<&Transform, With<Draggable>>,
     camera: Query<(&Camera, &GlobalTransform)>,
-    mut level: ResMut<Level>,
-    mut commands: Commands,
-    asset_server: Res<AssetServer>,
+    level: Res<Level>,
+    mut moves: EventWriter<MoveBlock>,
 ) {
     if event.button != PointerButton::Primary {
         return;
     }
     if let Ok(root) = root_query.get(event.target) {
-        if let Ok((mut transform, mut block)) = block_query.get_mut(root.0) {
+        if let Ok(transform) = block_query.get(root.0) {
             let (camera, camera_transform) = camera.single();
             if let Some(ray) =
                 camera.viewport_to_world(camera_transform, event.pointer_location.position)
             {
                 if let Some(dist) = ray.intersect_plane(transform.translation, Vec3::Y) {
-                    let pos = ray.get_point(dist);
-                    let mut dis = level.to_discrete(pos);
-                    let dist = dis.distance(block.position);
-                    if dist > 0 {
-                        if dist > 1 {
-                            let mut dx = (dis.x - block.position.x).signum();
-                            let mut dz = (dis.z - block.position.z).signum();
-                            if dx != 0 && dz != 0 {
-                                if fastrand::bool() {
-                                    dx = 0;
-                                } else {
-                                    dz = 0;
-                                }
-                            }
-                            dis = block.position + Dis2::new(dx, dz);
-                        }
-                        if level.try_place(&block, dis, block.rotation) {
-                            level.remove(&block);
-                            block.translate(dis);
-                            level.place(&block);
-                            transform.translation = level.to_vec3(dis);
-                            commands.spawn(AudioBundle {
-                                source: asset_server.load("sounds/click.ogg"),
-                                settings: PlaybackSettings {
-                                    mode: bevy::audio::PlaybackMode::Despawn,
-                                    volume: bevy::audio::Volume::new_relative(0.5),
-                                    speed: fastrand::f32() * 0.3 + 0.75,
-                                    paused: false,
-                                },
-                            });
-                        }
-                    }
+                    moves.send(MoveBlock {
+                        entity: root.0,
+                        target: level.to_discrete(ray.get_point(dist)),
+                    });
                 }
             }
         }
     }
 }
 
-fn on_click(
-    event: Listener<Pointer<Click>>,
+fn begin_gestures(
+    mut start: EventReader<Pointer<DragStart>>,
     root_query: Query<&Draggable>,
-    mut block_query: Query<(&mut Transform, &mut Block), With<Draggable>>,
+    mut history: ResMut<History>,
+) {
+    for ev in start.iter() {
+        if let Ok(root) = root_query.get(ev.target) {
+            history.begin_gesture(root.0);
+        }
+    }
+}
+
+/// Runs after `move_blocks`, so the last move of a drag still joins its gesture.
+fn end_gestures(mut end: EventReader<Pointer<DragEnd>>, mut history: ResMut<History>) {
+    if end.iter().count() > 0 {
+        history.end_gesture();
+    }
+}
+
+fn move_blocks(
+    mut events: EventReader<MoveBlock>,
+    mut block_query: Query<(&mut Transform, &mut Block)>,
     mut level: ResMut<Level>,
+    mut history: ResMut<History>,
+    mut score: ResMut<Score>,
     mut commands: Commands,
     asset_server: Res<AssetServer>,
 ) {
-    if event.button != PointerButton::Secondary {
-        return;
+    for ev in events.iter() {
+        let Ok((mut transform, mut block)) = block_query.get_mut(ev.entity) else {
+            continue;
+        };
+        let path = level.drag_path(&block, ev.target);
+        let Some(end) = path.last() else {
+            continue;
+        };
+        let from = (block.position, block.rotation);
+        let to = (*end, block.rotation);
+        // Slide on from wherever an earlier slide has got to
+        let mut points = vec![transform.translation];
+        points.extend(path.iter().map(|p| level.to_vec3(*p)));
+        set_block(&mut level, &mut block, &mut transform, to);
+        commands.entity(ev.entity).insert(Slide {
+            points,
+            progress: 0.0,
+        });
+        history.push(Move {
+            entity: ev.entity,
+            from,
+            to,
+        });
+        // Each tile counts as a move, like in the solver and the par
+        for _ in path.iter() {
+            score.count_move();
+        }
+        commands.spawn(AudioBundle {
+            source: asset_server.load("sounds/click.ogg"),
+            settings: PlaybackSettings {
+                mode: bevy::audio::PlaybackMode::Despawn,
+                volume: bevy::audio::Volume::new_relative(0.5),
+                speed: fastrand::f32() * 0.3 + 0.75,
+                paused: false,
+            },
+        });
     }
+}
+
+fn on_click(
+    event: Listener<Pointer<Click>>,
+    root_query: Query<&Draggable>,
+    keys: Res<Input<KeyCode>>,
+    mut rotate: EventWriter<RotateBlock>,
+) {
+    let shift = keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+    let Some(turn) = click_turn(event.button, shift) else {
+        return;
+    };
     if let Ok(root) = root_query.get(event.target) {
-        if let Ok((mut transform, mut block)) = block_query.get_mut(root.0) {
-            let mut rotation = block.rotation;
-            for _ in 0..3 {
-                rotation = rotation.left();
-                for pos in [Dis2::ZERO, Dis2::X, Dis2::Z, Dis2::NEG_X, Dis2::NEG_Z].iter() {
-                    let pos = block.position + *pos;
-                    if !block.iter().any(|p| p == pos) {
-                        continue;
-                    }
-                    if level.try_place(&block, pos, rotation) {
-                        level.remove(&block);
-                        block.rotate(rotation).translate(pos);
-                        level.place(&block);
-                        transform.translation = level.to_vec3(pos);
-                        transform.rotation = Quat::from_rotation_y(rotation.as_radians());
-                        commands.spawn(AudioBundle {
-                            source: asset_server.load("sounds/clank.ogg"),
-                            settings: PlaybackSettings {
-                                mode: bevy::audio::PlaybackMode::Despawn,
-                                volume: bevy::audio::Volume::new_relative(0.5),
-                                speed: fastrand::f32() * 0.2 + 0.9,
-                                paused: false,
-                            },
-                        });
-                        return;
-                    }
-                }
-            }
+        rotate.send(RotateBlock {
+            entity: root.0,
+            turn,
+        });
+    }
+}
+
+/// Right-click turns blocks left, and shift-right-click turns them right. Left-click is only for
+/// dragging.
+fn click_turn(button: PointerButton, shift: bool) -> Option<Turn> {
+    match (button, shift) {
+        (PointerButton::Secondary, false) => Some(Turn::Left),
+        (PointerButton::Secondary, true) => Some(Turn::Right),
+        _ => None,
+    }
+}
+
+/// Turn the block under the pointer with the mouse wheel, a quarter turn per notch.
+fn wheel_rotate(
+    mut wheel: EventReader<MouseWheel>,
+    hovered: Query<(&PickingInteraction, &Draggable)>,
+    mut rotate: EventWriter<RotateBlock>,
+    mut scrolled: Local<f32>,
+) {
+    let Some((_, root)) = hovered
+        .iter()
+        .find(|(i, _)| **i == PickingInteraction::Hovered)
+    else {
+        wheel.clear();
+        *scrolled = 0.0;
+        return;
+    };
+    for ev in wheel.iter() {
+        *scrolled += match ev.unit {
+            MouseScrollUnit::Line => ev.y,
+            MouseScrollUnit::Pixel => ev.y / 40.0,
+        };
+        while scrolled.abs() >= 1.0 {
+            let turn = if *scrolled > 0.0 {
+                Turn::Left
+            } else {
+                Turn::Right
+            };
+            *scrolled -= scrolled.signum();
+            rotate.send(RotateBlock {
+                entity: root.0,
+                turn,
+            });
+        }
+    }
+}
+
+fn rotate_blocks(
+    mut events: EventReader<RotateBlock>,
+    mut block_query: Query<(&mut Transform, &mut Block)>,
+    mut level: ResMut<Level>,
+    mut history: ResMut<History>,
+    mut score: ResMut<Score>,
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+) {
+    for ev in events.iter() {
+        let Ok((mut transform, mut block)) = block_query.get_mut(ev.entity) else {
+            continue;
+        };
+        let from = (block.position, block.rotation);
+        if let Some(to) = level
+            .try_rotate(&block, ev.turn)
+            .filter(|to| block.lock.allows(from, *to))
+        {
+            set_block(&mut level, &mut block, &mut transform, to);
+            commands.entity(ev.entity).remove::<Slide>();
+            history.push(Move {
+                entity: ev.entity,
+                from,
+                to,
+            });
+            score.count_move();
             commands.spawn(AudioBundle {
-                source: asset_server.load("sounds/boop.ogg"),
+                source: asset_server.load("sounds/clank.ogg"),
                 settings: PlaybackSettings {
                     mode: bevy::audio::PlaybackMode::Despawn,
                     volume: bevy::audio::Volume::new_relative(0.5),
@@ -468,7 +889,59 @@ fn on_click(
                     paused: false,
                 },
             });
+            continue;
         }
+        commands.spawn(AudioBundle {
+            source: asset_server.load("sounds/boop.ogg"),
+            settings: PlaybackSettings {
+                mode: bevy::audio::PlaybackMode::Despawn,
+                volume: bevy::audio::Volume::new_relative(0.5),
+                speed: fastrand::f32() * 0.2 + 0.9,
+                paused: false,
+            },
+        });
+    }
+}
+
+const GHOST_COLOR: Color = Color::rgba(1.0, 1.0, 1.0, 0.6);
+
+/// Outline where the block under the pointer would land if it was turned with a click.
+fn draw_rotation_ghost(
+    hovered: Query<(&PickingInteraction, &Draggable)>,
+    blocks: Query<&Block>,
+    keys: Res<Input<KeyCode>>,
+    level: Res<Level>,
+    mut gizmos: Gizmos,
+) {
+    let Some(block) = hovered
+        .iter()
+        .find(|(i, _)| **i == PickingInteraction::Hovered)
+        .and_then(|(_, root)| blocks.get(root.0).ok())
+    else {
+        return;
+    };
+    let from = (block.position, block.rotation);
+    let shift = keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+    let turn = click_turn(PointerButton::Secondary, shift).unwrap();
+    let Some((position, rotation)) = level
+        .try_rotate(block, turn)
+        .filter(|to| block.lock.allows(from, *to))
+    else {
+        return;
+    };
+    for tile in block.iter_with(position, rotation) {
+        let center = level.to_vec3(tile) + Vec3::Y * 1.1;
+        let corners = [
+            (-1.0, -1.0),
+            (1.0, -1.0),
+            (1.0, 1.0),
+            (-1.0, 1.0),
+            (-1.0, -1.0),
+        ];
+        gizmos.linestrip(
+            corners.map(|(x, z)| center + Vec3::new(x, 0.0, z) * 0.4),
+            GHOST_COLOR,
+        );
     }
 }
 
@@ -477,8 +950,19 @@ fn make_scene_draggable(
     mut query: Query<(Entity, &MakeSceneDraggable), With<Children>>,
     mesh_query: Query<Entity, (With<Parent>, With<Handle<Mesh>>)>,
     child_query: Query<&Children>,
+    blocks: Query<&Block>,
 ) {
     for (entity, drag) in query.iter_mut() {
+        // Fixed blocks are not picked at all, so they don't highlight either
+        if blocks
+            .get(drag.0.unwrap_or(entity))
+            .is_ok_and(|b| b.lock == Lock::Fixed)
+        {
+            if drag.0.is_none() {
+                commands.entity(entity).remove::<MakeSceneDraggable>();
+            }
+            continue;
+        }
         match drag.0 {
             Some(e) => {
                 make_pickable_recursive(&mut commands, &e, &entity, &child_query, &mesh_query)
@@ -498,6 +982,55 @@ fn make_scene_draggable(
     }
 }
 
+const LOCK_COLOR: Color = Color::rgb(0.25, 0.25, 0.3);
+
+/// Mark locked blocks: fixed blocks with a frame around each tile, blocks that can't rotate with
+/// a crossed circle, and sliding blocks with arrows along their axis.
+fn draw_locks(blocks: Query<&Block>, level: Res<Level>, mut gizmos: Gizmos) {
+    for block in blocks.iter() {
+        for tile in block.iter() {
+            let center = level.to_vec3(tile) + Vec3::Y * 1.05;
+            match block.lock {
+                Lock::Free => {}
+                Lock::Fixed => {
+                    let corners = [
+                        (-1.0, -1.0),
+                        (1.0, -1.0),
+                        (1.0, 1.0),
+                        (-1.0, 1.0),
+                        (-1.0, -1.0),
+                    ];
+                    gizmos.linestrip(
+                        corners.map(|(x, z)| center + Vec3::new(x, 0.0, z) * 0.45),
+                        LOCK_COLOR,
+                    );
+                }
+                Lock::NoRotation => {
+                    gizmos.circle(center, Vec3::Y, 0.3, LOCK_COLOR);
+                    gizmos.line(
+                        center + Vec3::new(-0.2, 0.0, -0.2),
+                        center + Vec3::new(0.2, 0.0, 0.2),
+                        LOCK_COLOR,
+                    );
+                }
+                Lock::SlideX | Lock::SlideZ => {
+                    let (along, across) = if block.lock == Lock::SlideX {
+                        (Vec3::X, Vec3::Z)
+                    } else {
+                        (Vec3::Z, Vec3::X)
+                    };
+                    gizmos.line(center - along * 0.4, center + along * 0.4, LOCK_COLOR);
+                    for end in [along * 0.4, -along * 0.4] {
+                        let back = center + end * 0.6;
+                        gizmos.line(center + end, back + across * 0.15, LOCK_COLOR);
+                        gizmos.line(center + end, back - across * 0.15, LOCK_COLOR);
+                    }
+                }
+            }
+        }
+    }
+}
+
 const HIGHLIGHT_TINT: Highlight<StandardMaterial> = Highlight {
     hovered: Some(HighlightKind::new_dynamic(|matl| StandardMaterial {
         base_color: matl.base_color + vec4(-0.1, 0.1, 0.4, 0.0),
@@ -545,9 +1078,9 @@ mod tests {
         assert_eq!(
             vec![
                 Dis2::new(0, 0),
-                Dis2::new(0, 1),
-                Dis2::new(0, 2),
-                Dis2::new(-1, 2)
+                Dis2::new(0, -1),
+                Dis2::new(0, -2),
+                Dis2::new(1, -2)
             ],
             obj.iter().collect::<Vec<Dis2>>()
         );
@@ -556,14 +1089,164 @@ mod tests {
         assert_eq!(
             vec![
                 Dis2::new(1, 0),
-                Dis2::new(1, 1),
-                Dis2::new(1, 2),
-                Dis2::new(0, 2)
+                Dis2::new(1, -1),
+                Dis2::new(1, -2),
+                Dis2::new(2, -2)
             ],
             obj.iter().collect::<Vec<Dis2>>()
         );
     }
 
+    #[test]
+    fn test_lock() {
+        let start = (Dis2::new(2, 2), Rotation::D0);
+        let right = (Dis2::new(3, 2), Rotation::D0);
+        let down = (Dis2::new(2, 3), Rotation::D0);
+        let turned = (Dis2::new(2, 2), Rotation::D90);
+        assert!(Lock::Free.allows(start, turned));
+        assert!(!Lock::Fixed.allows(start, right));
+        assert!(!Lock::Fixed.allows(start, turned));
+        assert!(Lock::NoRotation.allows(start, down));
+        assert!(!Lock::NoRotation.allows(start, turned));
+        assert!(Lock::SlideX.allows(start, right));
+        assert!(!Lock::SlideX.allows(start, down));
+        assert!(!Lock::SlideZ.allows(start, right));
+        assert!(Lock::SlideZ.allows(start, down));
+    }
+
+    #[test]
+    fn test_drag_path() {
+        let mut level = Level::new(5, 5);
+        for x in 0..5 {
+            for z in 0..5 {
+                level.set_floor(x, z);
+            }
+        }
+        // A wall between the block and the target
+        *level.getd_mut(Dis2::new(1, 1)).unwrap() = usize::MAX;
+        *level.getd_mut(Dis2::new(2, 1)).unwrap() = usize::MAX;
+        let block = Block::new(1, Dis2::new(1, 2)).with_tile(Dis2::X);
+        level.place(&block);
+
+        let path = level.drag_path(&block, Dis2::new(1, 0));
+        assert_eq!(
+            path,
+            vec![
+                Dis2::new(2, 2),
+                Dis2::new(3, 2),
+                Dis2::new(3, 1),
+                Dis2::new(3, 0),
+                Dis2::new(2, 0),
+                Dis2::new(1, 0),
+            ]
+        );
+        assert_eq!(level.drag_path(&block, Dis2::new(1, 2)), vec![]);
+        // Out of reach, so the nearest position is used
+        assert_eq!(
+            level.drag_path(&block, Dis2::new(1, 9)),
+            vec![Dis2::new(1, 3), Dis2::new(1, 4)]
+        );
+
+        let mut sliding = block.clone();
+        sliding.lock = Lock::SlideZ;
+        assert_eq!(level.drag_path(&sliding, Dis2::new(1, 0)), vec![]);
+        sliding.lock = Lock::SlideX;
+        assert_eq!(
+            level.drag_path(&sliding, Dis2::new(4, 0)),
+            vec![Dis2::new(2, 2), Dis2::new(3, 2)]
+        );
+    }
+
+    #[test]
+    fn test_history_gesture() {
+        let (a, b) = (Entity::from_raw(1), Entity::from_raw(2));
+        let at = |x| (Dis2::new(x, 0), Rotation::D0);
+        let step = |entity, from, to| Move {
+            entity,
+            from: at(from),
+            to: at(to),
+        };
+        let mut history = History::default();
+        history.push(step(a, 0, 1));
+
+        // One drag across several tiles is one undo step
+        history.begin_gesture(a);
+        for x in 1..5 {
+            history.push(step(a, x, x + 1));
+        }
+        history.end_gesture();
+        assert_eq!(history.undo.len(), 2);
+        assert_eq!((history.undo[1].from, history.undo[1].to), (at(1), at(5)));
+
+        // Moves after the gesture, or of other blocks, are steps of their own
+        history.push(step(a, 5, 6));
+        history.begin_gesture(b);
+        history.push(step(b, 0, 1));
+        history.push(step(b, 1, 2));
+        history.end_gesture();
+        assert_eq!(history.undo.len(), 4);
+
+        // Dragging back to the start undoes nothing
+        history.begin_gesture(a);
+        history.push(step(a, 6, 7));
+        history.push(step(a, 7, 6));
+        history.end_gesture();
+        assert_eq!(history.undo.len(), 4);
+    }
+
+    #[test]
+    fn test_click_turn() {
+        assert_eq!(click_turn(PointerButton::Primary, false), None);
+        assert_eq!(click_turn(PointerButton::Primary, true), None);
+        assert_eq!(click_turn(PointerButton::Middle, false), None);
+        assert_eq!(
+            click_turn(PointerButton::Secondary, false),
+            Some(Turn::Left)
+        );
+        assert_eq!(
+            click_turn(PointerButton::Secondary, true),
+            Some(Turn::Right)
+        );
+    }
+
+    #[test]
+    fn test_kicks() {
+        let mut level = Level::new(4, 4);
+        for x in 0..4 {
+            for z in 0..4 {
+                level.set_floor(x, z);
+            }
+        }
+        // A straight belt of three against the left wall
+        let block = Block::new(1, Dis2::new(0, 3))
+            .with_tile(Dis2::NEG_Z)
+            .with_tile(Dis2::new(0, -2));
+        level.place(&block);
+        // Turning right swings it away from the wall
+        assert_eq!(
+            level.try_rotate(&block, Turn::Right),
+            Some((Dis2::new(0, 3), Rotation::D270))
+        );
+        // Turning left swings it into the wall, further than the default kicks reach
+        assert_eq!(level.try_rotate(&block, Turn::Left), None);
+        level.kicks.left = vec![(0, 0), (2, 0)];
+        assert_eq!(
+            level.try_rotate(&block, Turn::Left),
+            Some((Dis2::new(2, 3), Rotation::D90))
+        );
+        // Kicks are relative to the block, so they turn with it
+        let mut turned = block.clone();
+        level.remove(&block);
+        turned.translate(Dis2::new(1, 0)).rotate(Rotation::D270);
+        level.place(&turned);
+        *level.getd_mut(Dis2::new(1, 2)).unwrap() = usize::MAX;
+        level.kicks.right = vec![(0, 0), (0, 1)];
+        assert_eq!(
+            level.try_rotate(&turned, Turn::Right),
+            Some((Dis2::new(0, 0), Rotation::D180))
+        );
+    }
+
     #[test]
     fn test_rot() {
         let f = Dis2::NEG_Z;
@@ -0,0 +1,368 @@
+//! Procedural generation of solvable levels.
+//!
+//! A level is generated by first routing every input to its output, cutting the routes into belts,
+//! and then scrambling the belts with random (reversible) moves. Since every move can be undone,
+//! the scrambled level is always solvable.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::game::{Block, Dis2, Level, Rotation};
+use crate::load::{LevelFile, Object};
+use crate::objects::BeltShape;
+use crate::palette::Palette;
+use crate::solver::Puzzle;
+
+#[derive(Debug, Clone, Copy)]
+pub struct GeneratorParams {
+    /// The width of the level, including the outer walls.
+    pub width: usize,
+    /// The height of the level, including the outer walls.
+    pub height: usize,
+    /// The number of input/output pairs, at most the number of colors in the palette.
+    pub colors: usize,
+    /// The number of belts, at least one per color.
+    pub belts: usize,
+    /// The chance for each floor tile that is not needed by the solution to become a wall.
+    pub wall_density: f32,
+}
+
+impl Default for GeneratorParams {
+    fn default() -> Self {
+        Self {
+            width: 12,
+            height: 10,
+            colors: 2,
+            belts: 6,
+            wall_density: 0.1,
+        }
+    }
+}
+
+const ATTEMPTS: usize = 100;
+const SCRAMBLE_MOVES: usize = 50;
+const SCRAMBLE_TARGETS: usize = 10;
+
+/// Generate a solvable level, or `None` if no level could be found for the given parameters.
+///
+/// The same parameters and seed always produce the same level.
+pub fn generate(params: &GeneratorParams, palette: &Palette, seed: u64) -> Option<LevelFile> {
+    if params.colors == 0
+        || params.colors > palette.colors.len()
+        || params.width < 4
+        || params.height < 4
+    {
+        return None;
+    }
+    let mut rng = fastrand::Rng::with_seed(seed);
+    (0..ATTEMPTS).find_map(|_| try_generate(params, palette, &mut rng))
+}
+
+fn try_generate(
+    params: &GeneratorParams,
+    palette: &Palette,
+    rng: &mut fastrand::Rng,
+) -> Option<LevelFile> {
+    let (width, height) = (params.width, params.height);
+    let mut layout = vec![vec!['#'; width]; height];
+    for row in layout.iter_mut().take(height - 1).skip(1) {
+        for c in row.iter_mut().take(width - 1).skip(1) {
+            *c = ' ';
+        }
+    }
+
+    // Route each color from its input to its output
+    let mut used = HashSet::new();
+    let mut routes = vec![];
+    for color in palette.colors.iter().take(params.colors) {
+        let (from, start) = border_tile(&layout, &used, rng)?;
+        *tile_mut(&mut layout, from) = color.input;
+        let (to, end) = border_tile(&layout, &used, rng)?;
+        *tile_mut(&mut layout, to) = color.output;
+        let path = route(&layout, &used, start, end, rng)?;
+        used.extend(path.iter().copied());
+        routes.push((from, path, to));
+    }
+
+    // Decide how many belts each route is cut into
+    let mut counts = vec![1; routes.len()];
+    for _ in 0..params.belts.saturating_sub(routes.len()) {
+        let open = (0..routes.len())
+            .filter(|i| counts[*i] < routes[*i].1.len())
+            .collect::<Vec<_>>();
+        if open.is_empty() {
+            break;
+        }
+        counts[open[rng.usize(..open.len())]] += 1;
+    }
+
+    for row in layout.iter_mut() {
+        for c in row.iter_mut() {
+            if *c == ' ' && rng.f32() < params.wall_density {
+                *c = '#';
+            }
+        }
+    }
+    used.iter().for_each(|d| *tile_mut(&mut layout, *d) = ' ');
+
+    let mut level = Level::new(width, height);
+    for (j, row) in layout.iter().enumerate() {
+        for (i, c) in row.iter().enumerate() {
+            if *c == ' ' {
+                level.set_floor(i, j);
+            }
+        }
+    }
+
+    // Cut the routes into belts, placed in their solved position
+    let mut blocks = vec![];
+    let mut paths = vec![];
+    for ((from, path, to), count) in routes.iter().zip(counts) {
+        let mut cuts = (1..path.len()).collect::<Vec<_>>();
+        rng.shuffle(&mut cuts);
+        cuts.truncate(count - 1);
+        cuts.push(0);
+        cuts.push(path.len());
+        cuts.sort();
+        for chunk in cuts.windows(2) {
+            let (start, end) = (chunk[0], chunk[1]);
+            let tile = |i: usize| match i {
+                0 => *from,
+                i if i > path.len() => *to,
+                i => path[i - 1],
+            };
+            let belt = (start + 1..=end)
+                .map(|i| {
+                    let dir_in = direction(tile(i - 1), tile(i));
+                    let dir_out = direction(tile(i), tile(i + 1));
+                    if dir_out == dir_in {
+                        'f'
+                    } else if dir_out == dir_in.left() {
+                        'l'
+                    } else {
+                        'r'
+                    }
+                })
+                .collect::<String>();
+            let mut block = Block::new(level.next_index(), path[start]);
+            block.tiles = BeltShape::new(&belt)?.tiles;
+            block.rotate(direction(tile(start), tile(start + 1)));
+            debug_assert!(block.iter().eq(path[start..end].iter().copied()));
+            level.place(&block);
+            blocks.push(block);
+            paths.push(belt);
+        }
+    }
+
+    // Scramble the belts, leaving them all unrotated, as level files can't store any rotation
+    for _ in 0..SCRAMBLE_MOVES {
+        let i = rng.usize(..blocks.len());
+        scramble(&mut level, &mut blocks[i], rng);
+    }
+    for block in blocks.iter_mut() {
+        if block.rotation() != Rotation::D0 {
+            scramble(&mut level, block, rng);
+        }
+    }
+    if blocks.iter().any(|b| b.rotation() != Rotation::D0) {
+        return None;
+    }
+
+    let mut objects: Vec<String> = vec![];
+    let mut indices = HashMap::new();
+    for (block, belt) in blocks.iter().zip(paths) {
+        let index = match objects.iter().position(|o| *o == belt) {
+            Some(index) => index,
+            None => {
+                objects.push(belt);
+                objects.len() - 1
+            }
+        };
+        indices.insert(block.position(), index);
+    }
+    if objects.len() > 10 {
+        return None;
+    }
+    for (dis, index) in indices {
+        *tile_mut(&mut layout, dis) = char::from_digit(index as u32, 10).unwrap();
+    }
+
+    let file = LevelFile {
+        layout: layout.iter().map(|row| row.iter().collect()).collect(),
+        objects: objects.into_iter().map(Object::Belt).collect(),
+        par: None,
+        objectives: vec![],
+        inputs: Default::default(),
+        outputs: Default::default(),
+        kicks: None,
+    };
+    if Puzzle::new(&file, palette).ok()?.is_solved() {
+        return None;
+    }
+    Some(file)
+}
+
+fn tile_mut(layout: &mut [Vec<char>], dis: Dis2) -> &mut char {
+    let (x, z): (isize, isize) = dis.into();
+    &mut layout[z as usize][x as usize]
+}
+
+fn direction(from: Dis2, to: Dis2) -> Rotation {
+    [Rotation::D0, Rotation::D90, Rotation::D180, Rotation::D270]
+        .into_iter()
+        .find(|r| from + r.as_discrete() == to)
+        .unwrap()
+}
+
+/// Pick a free wall tile on the border, returning it and the floor tile next to it.
+fn border_tile(
+    layout: &[Vec<char>],
+    used: &HashSet<Dis2>,
+    rng: &mut fastrand::Rng,
+) -> Option<(Dis2, Dis2)> {
+    let (width, height) = (layout[0].len() as isize, layout.len() as isize);
+    for _ in 0..ATTEMPTS {
+        let x = rng.isize(1..width - 1);
+        let z = rng.isize(1..height - 1);
+        let (tile, inner) = match rng.usize(..4) {
+            0 => (Dis2::new(x, 0), Dis2::new(x, 1)),
+            1 => (Dis2::new(x, height - 1), Dis2::new(x, height - 2)),
+            2 => (Dis2::new(0, z), Dis2::new(1, z)),
+            _ => (Dis2::new(width - 1, z), Dis2::new(width - 2, z)),
+        };
+        let (x, z): (isize, isize) = tile.into();
+        if layout[z as usize][x as usize] == '#' && !used.contains(&inner) {
+            return Some((tile, inner));
+        }
+    }
+    None
+}
+
+/// Find a shortest path between two floor tiles that avoids the tiles that are already used.
+fn route(
+    layout: &[Vec<char>],
+    used: &HashSet<Dis2>,
+    start: Dis2,
+    end: Dis2,
+    rng: &mut fastrand::Rng,
+) -> Option<Vec<Dis2>> {
+    let free = |dis: Dis2| {
+        let (x, z): (isize, isize) = dis.into();
+        x >= 0
+            && z >= 0
+            && layout
+                .get(z as usize)
+                .and_then(|row| row.get(x as usize))
+                .is_some_and(|c| *c == ' ')
+            && !used.contains(&dis)
+    };
+    if !free(start) || !free(end) {
+        return None;
+    }
+    let mut previous = HashMap::from([(start, start)]);
+    let mut queue = VecDeque::from([start]);
+    while let Some(dis) = queue.pop_front() {
+        if dis == end {
+            let mut path = vec![end];
+            while *path.last().unwrap() != start {
+                path.push(previous[path.last().unwrap()]);
+            }
+            path.reverse();
+            return Some(path);
+        }
+        let mut dirs = [Dis2::X, Dis2::NEG_X, Dis2::Z, Dis2::NEG_Z];
+        rng.shuffle(&mut dirs);
+        for next in dirs.map(|d| dis + d) {
+            if free(next) && !previous.contains_key(&next) {
+                previous.insert(next, dis);
+                queue.push_back(next);
+            }
+        }
+    }
+    None
+}
+
+/// Find every position and rotation a block can reach on its own, assuming it has been removed
+/// from the level.
+fn reachable(level: &Level, block: &Block) -> HashSet<(Dis2, Rotation)> {
+    let mut block = block.clone();
+    let start = (block.position(), block.rotation());
+    let mut visited = HashSet::from([start]);
+    let mut queue = VecDeque::from([start]);
+    while let Some((position, rotation)) = queue.pop_front() {
+        block.translate(position).rotate(rotation);
+        for next in Puzzle::moves(level, &block) {
+            if visited.insert(next) {
+                queue.push_back(next);
+            }
+        }
+    }
+    visited
+}
+
+/// Move a block to a random unrotated position, from which it can also move back.
+fn scramble(level: &mut Level, block: &mut Block, rng: &mut fastrand::Rng) {
+    let from = (block.position(), block.rotation());
+    level.remove(block);
+    let mut targets = reachable(level, block)
+        .into_iter()
+        .filter(|(_, r)| *r == Rotation::D0)
+        .collect::<Vec<_>>();
+    // Sort first, so the result doesn't depend on the iteration order of the set
+    targets.sort_by_key(|(p, _)| *p);
+    rng.shuffle(&mut targets);
+    for (position, rotation) in targets.into_iter().take(SCRAMBLE_TARGETS) {
+        block.translate(position).rotate(rotation);
+        if reachable(level, block).contains(&from) {
+            level.place(block);
+            return;
+        }
+    }
+    block.translate(from.0).rotate(from.1);
+    level.place(block);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::check::check_level;
+    use crate::solver::{solve, SolverResult};
+
+    #[test]
+    fn test_generate() {
+        let params = GeneratorParams::default();
+        let palette = Palette::default();
+        for seed in 0..20 {
+            let level = generate(&params, &palette, seed).unwrap();
+            assert_eq!(check_level(&level, &palette), vec![], "seed {}", seed);
+            assert!(!Puzzle::new(&level, &palette).unwrap().is_solved());
+            assert_eq!(
+                generate(&params, &palette, seed).unwrap().layout,
+                level.layout
+            );
+        }
+    }
+
+    #[test]
+    fn test_generate_solvable() {
+        let params = GeneratorParams {
+            width: 6,
+            height: 6,
+            colors: 1,
+            belts: 2,
+            wall_density: 0.0,
+        };
+        let palette = Palette::default();
+        for seed in 0..5 {
+            let level = generate(&params, &palette, seed).unwrap();
+            assert!(
+                matches!(
+                    solve(&level, &palette, 100_000).unwrap(),
+                    SolverResult::Solved(_)
+                ),
+                "seed {}: {:?}",
+                seed,
+                level.layout
+            );
+        }
+    }
+}
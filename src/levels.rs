@@ -1,9 +1,16 @@
 use std::f32::consts::*;
 
+use bevy::asset::LoadState;
 use bevy::pbr::CascadeShadowConfigBuilder;
 use bevy::prelude::*;
+use bevy::reflect::{TypePath, TypeUuid};
+use bevy_common_assets::ron::RonAssetPlugin;
+use serde::Deserialize;
 
-use crate::load::LoadLevel;
+use crate::generator::{generate, GeneratorParams};
+use crate::load::{LevelFile, LoadLevel};
+use crate::palette::Palette;
+use crate::save::SaveFile;
 use crate::ui::{ShowDialog, ShowVictory};
 use crate::AppState;
 
@@ -11,8 +18,13 @@ pub struct LevelManagerPlugin;
 
 impl Plugin for LevelManagerPlugin {
     fn build(&self, app: &mut App) {
-        app.add_state::<LevelState>()
-            .add_systems(Update, (animate_sun_direction, skip_level))
+        app.add_plugins(RonAssetPlugin::<CampaignFile>::new(&["campaign.ron"]))
+            .add_event::<LeaveLevel>()
+            .insert_resource(PuzzleSeed::today())
+            .add_state::<LevelState>()
+            .add_systems(Startup, load_campaign)
+            .add_systems(Update, animate_sun_direction)
+            .add_systems(Update, skip_level.run_if(in_state(AppState::Level)))
             .add_systems(OnEnter(AppState::Unloading), unload_level)
             .add_systems(OnEnter(AppState::Loading), (spawn_sun, load_level));
     }
@@ -21,41 +33,108 @@ impl Plugin for LevelManagerPlugin {
 #[derive(Component)]
 pub struct LevelEntity;
 
+/// Leave the current level without completing it.
+#[derive(Event)]
+pub struct LeaveLevel(pub LevelState);
+
+/// The current random puzzle: the daily puzzle when `n` is 0, otherwise the `n`th one after it.
+/// Everyone gets the same puzzles on the same day.
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PuzzleSeed {
+    /// The date, as a number like 20261018.
+    pub day: u64,
+    pub n: u64,
+}
+
+impl PuzzleSeed {
+    pub fn today() -> Self {
+        Self {
+            day: civil_date(days_since_epoch()),
+            n: 0,
+        }
+    }
+
+    /// The seed for the generator. The day and the count are hashed together, so the puzzles after
+    /// one day's daily puzzle never turn up as another day's.
+    pub fn seed(&self) -> u64 {
+        mix(mix(self.day) ^ self.n)
+    }
+}
+
+impl std::fmt::Display for PuzzleSeed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.n == 0 {
+            write!(f, "Daily puzzle {}", self.day)
+        } else {
+            write!(f, "Puzzle {}-{}", self.day, self.n)
+        }
+    }
+}
+
+/// The splitmix64 finalizer, which spreads every input bit over the whole output.
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+const MAIN_MENU: &str = "levels/main_menu.ron";
+
 #[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash, States)]
 pub enum LevelState {
     #[default]
     MainMenu,
-    Level00,
-    Level01,
-    Level02,
-    Level03,
-    Test,
-}
-
-impl LevelState {
-    pub fn get_path(&self) -> &'static str {
-        match self {
-            LevelState::MainMenu => "levels/main_menu.ron",
-            LevelState::Level00 => "levels/level_00.ron",
-            LevelState::Level01 => "levels/level_01.ron",
-            LevelState::Level02 => "levels/level_02.ron",
-            LevelState::Level03 => "levels/level_03.ron",
-            LevelState::Test => "levels/test.ron",
-        }
-    }
+    Campaign,
+    Editor,
+    Random,
+}
 
-    pub fn next(&self) -> Self {
-        match self {
-            LevelState::MainMenu => LevelState::Level00,
-            LevelState::Level00 => LevelState::Level01,
-            LevelState::Level01 => LevelState::Level02,
-            LevelState::Level02 => LevelState::Level03,
-            LevelState::Level03 => LevelState::MainMenu,
-            LevelState::Test => LevelState::MainMenu,
-        }
+#[derive(Deserialize, TypeUuid, TypePath)]
+#[uuid = "8c1f5f0e-6a3d-4a59-9d57-2f4f6c2b7e1a"]
+pub struct CampaignFile {
+    pub levels: Vec<CampaignLevel>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct CampaignLevel {
+    pub path: String,
+    pub title: String,
+    #[serde(default)]
+    pub intro: Option<String>,
+}
+
+#[derive(Resource, Default)]
+pub struct Campaign {
+    pub file: Handle<CampaignFile>,
+    pub level: usize,
+}
+
+impl Campaign {
+    pub fn current<'a>(&self, campaigns: &'a Assets<CampaignFile>) -> Option<&'a CampaignLevel> {
+        campaigns
+            .get(&self.file)
+            .and_then(|c| c.levels.get(self.level))
     }
 }
 
+/// Whether the campaign file has loaded or failed to load. Levels wait for it, so a campaign
+/// without the level asked for can be told apart from one that hasn't loaded yet.
+pub fn campaign_ready(campaign: Option<Res<Campaign>>, asset_server: Res<AssetServer>) -> bool {
+    campaign.is_some_and(|c| {
+        matches!(
+            asset_server.get_load_state(&c.file),
+            LoadState::Loaded | LoadState::Failed
+        )
+    })
+}
+
+fn load_campaign(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(Campaign {
+        file: asset_server.load("levels/default.campaign.ron"),
+        level: 0,
+    });
+}
+
 fn spawn_sun(mut commands: Commands) {
     commands.spawn((
         DirectionalLightBundle {
@@ -77,42 +156,177 @@ fn spawn_sun(mut commands: Commands) {
     ));
 }
 
+#[allow(clippy::too_many_arguments)]
 fn unload_level(
     query: Query<Entity, (Without<Parent>, With<LevelEntity>)>,
     mut cmds: Commands,
     level: Res<State<LevelState>>,
     mut next_level: ResMut<NextState<LevelState>>,
     mut next_state: ResMut<NextState<AppState>>,
+    mut campaign: ResMut<Campaign>,
+    campaigns: Res<Assets<CampaignFile>>,
+    mut save: ResMut<SaveFile>,
+    mut leave: EventReader<LeaveLevel>,
+    mut dialog: EventWriter<ShowDialog>,
+    mut seed: ResMut<PuzzleSeed>,
+    asset_server: Res<AssetServer>,
 ) {
     for entity in query.iter() {
         cmds.entity(entity).despawn_recursive();
     }
-    next_level.set(level.next());
+    if let Some(LeaveLevel(target)) = leave.iter().last() {
+        if *target == LevelState::Random && **level != LevelState::Random {
+            *seed = PuzzleSeed::today();
+        }
+        next_level.set(*target);
+        next_state.set(AppState::Loading);
+        return;
+    }
+    let levels = campaigns.get(&campaign.file).map_or(0, |c| c.levels.len());
+    match **level {
+        LevelState::MainMenu => {
+            if asset_server.get_load_state(&campaign.file) == LoadState::Failed {
+                dialog.send(ShowDialog("Could not load the campaign".to_string()));
+            } else if levels == 0 {
+                dialog.send(ShowDialog("The campaign has no levels to play".to_string()));
+            }
+            campaign.level = if save.progress < levels {
+                save.progress
+            } else {
+                0
+            }
+        }
+        LevelState::Campaign => {
+            if let Some(current) = campaign.current(&campaigns) {
+                save.complete(campaign.level, &current.path);
+            }
+            campaign.level += 1;
+        }
+        LevelState::Editor => {
+            next_level.set(LevelState::MainMenu);
+            next_state.set(AppState::Loading);
+            return;
+        }
+        LevelState::Random => {
+            // Keep generating new puzzles until the player leaves
+            seed.n += 1;
+            next_state.set(AppState::Loading);
+            return;
+        }
+    }
+    if campaign.level < levels {
+        next_level.set(LevelState::Campaign);
+    } else {
+        next_level.set(LevelState::MainMenu);
+    }
     next_state.set(AppState::Loading);
 }
 
+#[allow(clippy::too_many_arguments)]
 fn load_level(
     level: Res<State<LevelState>>,
     mut commands: Commands,
     asset_server: Res<AssetServer>,
     mut dialog: EventWriter<ShowDialog>,
     mut victory: ResMut<ShowVictory>,
+    campaign: Res<Campaign>,
+    campaigns: Res<Assets<CampaignFile>>,
+    mut levels: ResMut<Assets<LevelFile>>,
+    mut next_state: ResMut<NextState<AppState>>,
+    mut leave: EventWriter<LeaveLevel>,
+    palette: Res<Palette>,
+    seed: Res<PuzzleSeed>,
 ) {
-    commands.insert_resource(LoadLevel(asset_server.load(level.get_path())));
     match **level {
-        LevelState::MainMenu => victory.disable(),
-        LevelState::Level00 => {
-            dialog.send(ShowDialog("Use WASD/arrows + QE to look around.\nDrag conveyor belts with your mouse.\nRight click to rotate them (if there is enough space).".to_string()));
+        LevelState::MainMenu => {
+            commands.insert_resource(LoadLevel(asset_server.load(MAIN_MENU)));
+            victory.disable();
+        }
+        LevelState::Editor => {
+            commands.insert_resource(LoadLevel::default());
+            victory.disable();
+            next_state.set(AppState::Editor);
+        }
+        LevelState::Campaign => {
+            let Some(level) = campaign.current(&campaigns) else {
+                dialog.send(ShowDialog("Could not find the campaign level".to_string()));
+                leave.send(LeaveLevel(LevelState::MainMenu));
+                next_state.set(AppState::Unloading);
+                return;
+            };
+            commands.insert_resource(LoadLevel(asset_server.load(&level.path)));
+            if let Some(intro) = &level.intro {
+                dialog.send(ShowDialog(intro.clone()));
+            }
         }
-        _ => {}
+        LevelState::Random => match generate(&GeneratorParams::default(), &palette, seed.seed()) {
+            Some(file) => {
+                commands.insert_resource(LoadLevel(levels.add(file)));
+            }
+            None => {
+                dialog.send(ShowDialog("Could not generate a puzzle".to_string()));
+                leave.send(LeaveLevel(LevelState::MainMenu));
+                next_state.set(AppState::Unloading);
+            }
+        },
     };
 }
 
-fn skip_level(mut keys: ResMut<Input<KeyCode>>, mut state: ResMut<NextState<AppState>>) {
-    if keys.just_pressed(KeyCode::K) {
-        keys.reset(KeyCode::K);
-        state.set(AppState::Unloading);
+/// Skipping a campaign level moves on without marking it as completed.
+fn skip_level(
+    mut keys: ResMut<Input<KeyCode>>,
+    mut state: ResMut<NextState<AppState>>,
+    level: Res<State<LevelState>>,
+    mut campaign: ResMut<Campaign>,
+    campaigns: Res<Assets<CampaignFile>>,
+    mut leave: EventWriter<LeaveLevel>,
+) {
+    if !keys.just_pressed(KeyCode::K) {
+        return;
     }
+    keys.reset(KeyCode::K);
+    if **level == LevelState::Campaign {
+        campaign.level += 1;
+        if campaign.current(&campaigns).is_some() {
+            leave.send(LeaveLevel(LevelState::Campaign));
+        } else {
+            leave.send(LeaveLevel(LevelState::MainMenu));
+        }
+    }
+    state.set(AppState::Unloading);
+}
+
+/// The days since 1970-01-01, in UTC.
+#[cfg(not(target_arch = "wasm32"))]
+fn days_since_epoch() -> u64 {
+    let seconds = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    seconds / 86400
+}
+
+/// The days since 1970-01-01, in UTC. `SystemTime` isn't available in the browser.
+#[cfg(target_arch = "wasm32")]
+fn days_since_epoch() -> u64 {
+    (js_sys::Date::now() / 86_400_000.0) as u64
+}
+
+/// Turns days since 1970-01-01 into the civil date as a number like 20261018.
+fn civil_date(days: u64) -> u64 {
+    // Count from 0000-03-01 so leap days fall at the end of each year
+    let days = days + 719468;
+    let (era, day_of_era) = (days / 146097, days % 146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let (year, month) = if month < 10 {
+        (era * 400 + year_of_era, month + 3)
+    } else {
+        (era * 400 + year_of_era + 1, month - 9)
+    };
+    year * 10000 + month * 100 + day
 }
 
 fn animate_sun_direction(
@@ -130,3 +344,33 @@ fn animate_sun_direction(
         );
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_civil_date() {
+        assert_eq!(civil_date(0), 19700101);
+        assert_eq!(civil_date(11016), 20000229);
+        assert_eq!(civil_date(19782), 20240229);
+        assert_eq!(civil_date(20379), 20251018);
+    }
+
+    #[test]
+    fn test_puzzle_seed() {
+        let daily = PuzzleSeed {
+            day: 20261031,
+            n: 0,
+        };
+        let next = PuzzleSeed { n: 1, ..daily };
+        let tomorrow = PuzzleSeed {
+            day: 20261101,
+            n: 0,
+        };
+        assert_ne!(daily.seed(), next.seed());
+        assert_ne!(next.seed(), tomorrow.seed());
+        assert_eq!(daily.to_string(), "Daily puzzle 20261031");
+        assert_eq!(next.to_string(), "Puzzle 20261031-1");
+    }
+}
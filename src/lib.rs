@@ -0,0 +1,29 @@
+use bevy::prelude::*;
+
+pub mod camera;
+pub mod check;
+pub mod controls;
+pub mod cubes;
+pub mod editor;
+pub mod game;
+pub mod generator;
+pub mod levels;
+pub mod load;
+pub mod objectives;
+pub mod objects;
+pub mod palette;
+pub mod routing;
+pub mod save;
+pub mod score;
+pub mod select;
+pub mod solver;
+pub mod ui;
+
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash, States)]
+pub enum AppState {
+    #[default]
+    Loading,
+    Level,
+    Editor,
+    Unloading,
+}
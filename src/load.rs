@@ -1,15 +1,24 @@
+use std::collections::BTreeMap;
 use std::f32::consts::PI;
 
+use bevy::asset::LoadState;
 use bevy::prelude::*;
 use bevy::reflect::{TypePath, TypeUuid};
 use bevy_common_assets::ron::RonAssetPlugin;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 use crate::camera::Unobstruct;
-use crate::cubes::{CubeColor, CubeProcessor, CubeRouter, CubeSpawner};
-use crate::game::Level;
-use crate::levels::LevelEntity;
-use crate::objects::BeltBuilder;
+use crate::cubes::{CubeColor, CubeProcessor, CubeRouter, CubeSpawner, SpawnSettings};
+use crate::game::{Block, Dis2, KickTable, Level, Lock};
+use crate::levels::{campaign_ready, LeaveLevel, LevelEntity, LevelState};
+use crate::objectives::Objective;
+use crate::objects::{
+    build_crate, build_merger, build_painter, build_splitter, crate_tiles, BeltBuilder, BeltShape,
+    ObjectShape,
+};
+use crate::palette::{palette_ready, update_palette, Palette};
+use crate::routing::{input_edge, output_edge};
+use crate::ui::ShowDialog;
 use crate::AppState;
 
 pub struct LoadPlugin;
@@ -18,18 +27,39 @@ impl Plugin for LoadPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins(RonAssetPlugin::<LevelFile>::new(&["ron"]))
             .init_resource::<LoadLevel>()
-            .add_systems(PreUpdate, load_level.run_if(in_state(AppState::Loading)));
+            .add_systems(
+                PreUpdate,
+                load_level.after(update_palette).run_if(
+                    in_state(AppState::Loading)
+                        .and_then(palette_ready)
+                        .and_then(campaign_ready),
+                ),
+            );
     }
 }
 
 #[derive(Resource, Default)]
 pub struct LoadLevel(pub Handle<LevelFile>);
 
-#[derive(Deserialize, TypeUuid, TypePath)]
+#[derive(Serialize, Deserialize, TypeUuid, TypePath)]
 #[uuid = "413be529-bfeb-41b3-9db0-4b8b380a2c46"]
 pub struct LevelFile {
     pub layout: Vec<String>,
     pub objects: Vec<Object>,
+    /// The number of moves needed for a good solution.
+    #[serde(default)]
+    pub par: Option<usize>,
+    #[serde(default)]
+    pub objectives: Vec<Objective>,
+    /// How the inputs spawn cubes, keyed by the column and row of the input.
+    #[serde(default)]
+    pub inputs: BTreeMap<(usize, usize), SpawnSettings>,
+    /// The colors of the outputs, keyed by their column and row, overriding the tile letter.
+    #[serde(default)]
+    pub outputs: BTreeMap<(usize, usize), CubeColor>,
+    /// The offsets tried when rotating blocks, instead of the default kicks.
+    #[serde(default)]
+    pub kicks: Option<KickTable>,
 }
 
 #[derive(Default, Clone, Debug)]
@@ -39,20 +69,49 @@ pub enum Tile {
     Wall(f32, f32, f32, f32),
     Floor(Object),
     Loadingbay,
-    Input(f32, CubeColor),
+    Input(f32, SpawnSettings),
     Output(f32, CubeColor),
     Door(f32, f32, f32, f32, f32),
 }
 
-#[derive(Deserialize, Default, Clone, Debug)]
+#[derive(Serialize, Deserialize, Default, Clone, Debug, PartialEq, Eq)]
 pub enum Object {
     #[default]
     Empty,
     Belt(String),
+    /// Splits one line of cubes into two, alternating between the left and the right.
+    Splitter,
+    /// Merges lines of cubes coming from the left and the right.
+    Merger,
+    /// Repaints cubes of the first color to the second color.
+    Painter(CubeColor, CubeColor),
+    /// A movable obstacle, with the tiles it covers relative to its tile in the layout, like
+    /// `Crate([(0, 0), (1, 0), (1, 1)])`.
+    Crate(Vec<(isize, isize)>),
+    /// Limits how the player can move an object, like `Locked(Fixed, Belt("ff"))`.
+    Locked(Lock, Box<Object>),
+}
+
+impl Object {
+    /// The object without any lock.
+    pub fn unlocked(&self) -> &Object {
+        match self {
+            Object::Locked(_, object) => object.unlocked(),
+            object => object,
+        }
+    }
+
+    /// How the player can move the object. With nested locks, the outermost one applies.
+    pub fn lock(&self) -> Lock {
+        match self {
+            Object::Locked(lock, _) => *lock,
+            _ => Lock::Free,
+        }
+    }
 }
 
 impl Tile {
-    fn is_floor(&self) -> bool {
+    pub fn is_floor(&self) -> bool {
         matches!(self, Tile::Floor(_) | Tile::Loadingbay)
     }
 
@@ -68,59 +127,264 @@ impl Tile {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LevelLoadError {
+    Unreadable,
+    EmptyLayout,
+    UnknownTile {
+        row: usize,
+        column: usize,
+        tile: char,
+    },
+    MissingObject {
+        row: usize,
+        column: usize,
+        index: usize,
+    },
+    UnknownDirection {
+        row: usize,
+        column: usize,
+        direction: char,
+    },
+    OutOfBounds {
+        row: usize,
+        column: usize,
+    },
+    /// A crate whose tiles don't form a single piece including its tile in the layout.
+    InvalidCrate {
+        row: usize,
+        column: usize,
+    },
+    /// Input or output settings for a tile that is not an input or output.
+    MisplacedSettings {
+        row: usize,
+        column: usize,
+    },
+    /// A color that is not in the palette, most likely misspelled.
+    UnknownColor {
+        row: usize,
+        column: usize,
+        name: String,
+    },
+}
+
+impl std::fmt::Display for LevelLoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LevelLoadError::Unreadable => write!(f, "The level file could not be read"),
+            LevelLoadError::EmptyLayout => write!(f, "The level layout is empty"),
+            LevelLoadError::UnknownTile { row, column, tile } => {
+                write!(
+                    f,
+                    "Unknown tile '{}' at row {}, column {}",
+                    tile, row, column
+                )
+            }
+            LevelLoadError::MissingObject { row, column, index } => write!(
+                f,
+                "Missing object {} at row {}, column {}",
+                index, row, column
+            ),
+            LevelLoadError::UnknownDirection {
+                row,
+                column,
+                direction,
+            } => write!(
+                f,
+                "Unknown belt direction '{}' at row {}, column {}",
+                direction, row, column
+            ),
+            LevelLoadError::OutOfBounds { row, column } => write!(
+                f,
+                "The object at row {}, column {} extends outside the level",
+                row, column
+            ),
+            LevelLoadError::InvalidCrate { row, column } => write!(
+                f,
+                "The crate at row {}, column {} is not a single piece covering its own tile",
+                row, column
+            ),
+            LevelLoadError::MisplacedSettings { row, column } => write!(
+                f,
+                "There are settings for row {}, column {}, which is not an input or output",
+                row, column
+            ),
+            LevelLoadError::UnknownColor { row, column, name } => write!(
+                f,
+                "Unknown color '{}' at row {}, column {}",
+                name, row, column
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LevelLoadError {}
+
+#[allow(clippy::too_many_arguments)]
 fn load_level(
-    level: Res<LoadLevel>,
+    mut level: ResMut<LoadLevel>,
     cmds: Commands,
     asset_server: Res<AssetServer>,
     assets_level: ResMut<Assets<LevelFile>>,
     mut state: ResMut<NextState<AppState>>,
+    mut dialog: EventWriter<ShowDialog>,
+    mut leave: EventWriter<LeaveLevel>,
+    level_state: Res<State<LevelState>>,
+    palette: Res<Palette>,
 ) {
-    if let Some(level) = assets_level.get(&level.0) {
-        let mut level = level_parse(level);
-        level_surround(&mut level);
-        level_spawn(level, cmds, asset_server);
-        state.set(AppState::Level);
+    let layout = match assets_level.get(&level.0) {
+        Some(file) => level_parse(file, &palette),
+        None if asset_server.get_load_state(&level.0) == LoadState::Failed => {
+            Err(LevelLoadError::Unreadable)
+        }
+        None => return,
+    };
+    match layout {
+        Ok(mut layout) => {
+            level_surround(&mut layout);
+            let kicks = assets_level.get(&level.0).and_then(|f| f.kicks.clone());
+            level_spawn(layout, kicks, cmds, asset_server, &palette);
+            state.set(AppState::Level);
+        }
+        Err(e) => {
+            error!("Could not load level: {}", e);
+            dialog.send(ShowDialog(format!("Could not load level:\n{}", e)));
+            *level = LoadLevel::default();
+            // There is nothing to fall back to if the main menu is broken
+            if **level_state != LevelState::MainMenu {
+                leave.send(LeaveLevel(LevelState::MainMenu));
+                state.set(AppState::Unloading);
+            }
+        }
     }
 }
 
-fn level_parse(level: &LevelFile) -> Vec<Vec<Tile>> {
-    level
+pub fn level_parse(level: &LevelFile, palette: &Palette) -> Result<Vec<Vec<Tile>>, LevelLoadError> {
+    if level.layout.is_empty() {
+        return Err(LevelLoadError::EmptyLayout);
+    }
+    let height = level.layout.len() as isize;
+    let width = level
         .layout
         .iter()
-        .map(|row| {
-            row.chars()
-                .map(|c| match c {
-                    ' ' => Tile::Floor(Object::Empty),
-                    '_' => Tile::Floor(Object::Empty),
-                    'E' => Tile::Empty,
-                    'L' => Tile::Loadingbay,
-                    'I' => Tile::Input(0.0, CubeColor::Green),
-                    'O' => Tile::Output(0.0, CubeColor::Green),
-                    'i' => Tile::Input(0.0, CubeColor::Purple),
-                    'o' => Tile::Output(0.0, CubeColor::Purple),
-                    'N' => Tile::Input(0.0, CubeColor::Yellow),
-                    'U' => Tile::Output(0.0, CubeColor::Yellow),
-                    'n' => Tile::Input(0.0, CubeColor::Black),
-                    'u' => Tile::Output(0.0, CubeColor::Black),
-                    '#' => Tile::Wall(0.0, 0.0, 0.0, 0.0),
-                    '0' => Tile::Floor(level.objects[0].clone()),
-                    '1' => Tile::Floor(level.objects[1].clone()),
-                    '2' => Tile::Floor(level.objects[2].clone()),
-                    '3' => Tile::Floor(level.objects[3].clone()),
-                    '4' => Tile::Floor(level.objects[4].clone()),
-                    '5' => Tile::Floor(level.objects[5].clone()),
-                    '6' => Tile::Floor(level.objects[6].clone()),
-                    '7' => Tile::Floor(level.objects[7].clone()),
-                    '8' => Tile::Floor(level.objects[8].clone()),
-                    '9' => Tile::Floor(level.objects[9].clone()),
-                    _ => panic!("Unknown tile: '{}'", c),
-                })
-                .collect()
-        })
-        .collect()
+        .map(|row| row.chars().count())
+        .max()
+        .unwrap_or_default() as isize;
+    let mut layout = Vec::with_capacity(level.layout.len());
+    for (row, line) in level.layout.iter().enumerate() {
+        let mut tiles = Vec::with_capacity(line.len());
+        for (column, c) in line.chars().enumerate() {
+            // The settings only fill in the colors they leave out from the tile letter
+            let input = |color| {
+                let mut settings = level
+                    .inputs
+                    .get(&(column, row))
+                    .cloned()
+                    .unwrap_or_else(|| SpawnSettings::new(color));
+                if settings.colors.is_empty() {
+                    settings.colors.push(color);
+                }
+                Tile::Input(0.0, settings)
+            };
+            let output =
+                |color| Tile::Output(0.0, *level.outputs.get(&(column, row)).unwrap_or(&color));
+            let tile = match c {
+                ' ' => Tile::Floor(Object::Empty),
+                '_' => Tile::Floor(Object::Empty),
+                'E' => Tile::Empty,
+                'L' => Tile::Loadingbay,
+                '#' => Tile::Wall(0.0, 0.0, 0.0, 0.0),
+                '0'..='9' => {
+                    let index = c.to_digit(10).unwrap() as usize;
+                    let object = level
+                        .objects
+                        .get(index)
+                        .ok_or(LevelLoadError::MissingObject { row, column, index })?;
+                    if let Object::Belt(path) = object.unlocked() {
+                        BeltShape::new(path).ok_or_else(|| LevelLoadError::UnknownDirection {
+                            row,
+                            column,
+                            direction: path
+                                .chars()
+                                .find(|c| !"fFlLrR".contains(*c))
+                                .unwrap_or_default(),
+                        })?;
+                    }
+                    if let Object::Painter(from, to) = object.unlocked() {
+                        if let Some(color) =
+                            [from, to].into_iter().find(|c| palette.get(**c).is_none())
+                        {
+                            return Err(LevelLoadError::UnknownColor {
+                                row,
+                                column,
+                                name: color.to_string(),
+                            });
+                        }
+                    }
+                    if let Object::Crate(tiles) = object.unlocked() {
+                        crate_tiles(tiles).ok_or(LevelLoadError::InvalidCrate { row, column })?;
+                    }
+                    if let Some(shape) = ObjectShape::new(object) {
+                        let origin: Dis2 = (column, row).into();
+                        let outside = shape.tiles.iter().any(|t| {
+                            let (x, z) = (*t + origin).into();
+                            x < 0 || z < 0 || x >= width || z >= height
+                        });
+                        if outside {
+                            return Err(LevelLoadError::OutOfBounds { row, column });
+                        }
+                    }
+                    match object.unlocked() {
+                        Object::Empty => Tile::Floor(Object::Empty),
+                        _ => Tile::Floor(object.clone()),
+                    }
+                }
+                _ => match (palette.input(c), palette.output(c)) {
+                    (Some(color), _) => input(color),
+                    (_, Some(color)) => output(color),
+                    _ => {
+                        return Err(LevelLoadError::UnknownTile {
+                            row,
+                            column,
+                            tile: c,
+                        })
+                    }
+                },
+            };
+            tiles.push(tile);
+        }
+        layout.push(tiles);
+    }
+    let tile = |column: usize, row: usize| layout.get(row).and_then(|r: &Vec<Tile>| r.get(column));
+    for &(column, row) in level.inputs.keys() {
+        if !matches!(tile(column, row), Some(Tile::Input(_, _))) {
+            return Err(LevelLoadError::MisplacedSettings { row, column });
+        }
+    }
+    for &(column, row) in level.outputs.keys() {
+        if !matches!(tile(column, row), Some(Tile::Output(_, _))) {
+            return Err(LevelLoadError::MisplacedSettings { row, column });
+        }
+    }
+    // Any name makes a color, so names missing from the palette are most likely typos
+    let colors = level
+        .inputs
+        .iter()
+        .flat_map(|(pos, s)| s.colors.iter().map(move |c| (pos, c)))
+        .chain(level.outputs.iter());
+    for (&(column, row), color) in colors {
+        if palette.get(*color).is_none() {
+            return Err(LevelLoadError::UnknownColor {
+                row,
+                column,
+                name: color.to_string(),
+            });
+        }
+    }
+    Ok(layout)
 }
 
-fn level_surround(layout: &mut Vec<Vec<Tile>>) {
+pub fn level_surround(layout: &mut Vec<Vec<Tile>>) {
     let get = |layout: &Vec<Vec<Tile>>, i: isize, j: isize| {
         if i < 0 || j < 0 {
             return Tile::Empty;
@@ -178,12 +442,13 @@ fn level_surround(layout: &mut Vec<Vec<Tile>>) {
                     }
                 }
                 Tile::Input(_, t) => {
+                    let t = t.clone();
                     if get(layout, x + 1, z).is_floor() {
-                        layout[j][i] = Tile::Input(PI, *t)
+                        layout[j][i] = Tile::Input(PI, t)
                     } else if get(layout, x, z + 1).is_floor() {
-                        layout[j][i] = Tile::Input(PI * 0.5, *t)
+                        layout[j][i] = Tile::Input(PI * 0.5, t)
                     } else if get(layout, x, z - 1).is_floor() {
-                        layout[j][i] = Tile::Input(-PI * 0.5, *t)
+                        layout[j][i] = Tile::Input(-PI * 0.5, t)
                     }
                 }
                 Tile::Output(_, t) => {
@@ -201,13 +466,21 @@ fn level_surround(layout: &mut Vec<Vec<Tile>>) {
     }
 }
 
-fn level_spawn(layout: Vec<Vec<Tile>>, mut cmds: Commands, asset_server: Res<AssetServer>) {
+pub fn level_spawn(
+    layout: Vec<Vec<Tile>>,
+    kicks: Option<KickTable>,
+    mut cmds: Commands,
+    asset_server: Res<AssetServer>,
+    palette: &Palette,
+) {
     let floor = asset_server.load("models/floor.glb#Scene0");
     let loadingbay = asset_server.load("models/loadingbay.glb#Scene0");
     let wall = asset_server.load("models/wall.glb#Scene0");
     let door = asset_server.load("models/door.glb#Scene0");
 
-    let mut level = Level::new(layout[0].len(), layout.len());
+    let width = layout.iter().map(|row| row.len()).max().unwrap_or_default();
+    let mut level = Level::new(width, layout.len());
+    level.kicks = kicks.unwrap_or_default();
     let offset = level.offset();
 
     for (j, row) in layout.into_iter().enumerate() {
@@ -235,7 +508,16 @@ fn level_spawn(layout: Vec<Vec<Tile>>, mut cmds: Commands, asset_server: Res<Ass
                             ..Default::default()
                         },
                     ));
-                    spawn_object(object, i, j, pos, &mut level, &mut cmds, &asset_server);
+                    spawn_object(
+                        object,
+                        i,
+                        j,
+                        pos,
+                        &mut level,
+                        &mut cmds,
+                        &asset_server,
+                        palette,
+                    );
                 }
                 Tile::Loadingbay => {
                     level.set_floor(i, j);
@@ -248,30 +530,40 @@ fn level_spawn(layout: Vec<Vec<Tile>>, mut cmds: Commands, asset_server: Res<Ass
                         LevelEntity,
                     ));
                 }
-                Tile::Input(rot, color) => {
-                    // TODO color based on type
+                Tile::Input(rot, settings) => {
+                    let edge = input_edge(rot, (i, j).into());
+                    for (k, color) in settings.colors.iter().enumerate() {
+                        if !settings.colors[..k].contains(color) {
+                            level.add_input(edge, *color);
+                        }
+                    }
+                    let (scene, tint) = palette.io_model(settings.colors[0], &asset_server);
                     cmds.spawn((
                         SceneBundle {
-                            scene: asset_server.load(color.io_path()),
+                            scene,
                             transform: Transform::from_translation(pos)
                                 .with_rotation(Quat::from_rotation_y(rot + PI * 0.5)),
                             ..Default::default()
                         },
-                        CubeSpawner::new(Vec3::Y, 2.0, color),
-                        CubeRouter(vec![Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, 1.0, -0.5)]),
+                        tint,
+                        CubeSpawner::new(Vec3::Y, settings),
+                        CubeRouter::new(vec![Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, 1.0, -0.5)]),
                         LevelEntity,
                     ));
                 }
                 Tile::Output(rot, color) => {
+                    level.add_output(output_edge(rot, (i, j).into()), color);
+                    let (scene, tint) = palette.io_model(color, &asset_server);
                     cmds.spawn((
                         SceneBundle {
-                            scene: asset_server.load(color.io_path()),
+                            scene,
                             transform: Transform::from_translation(pos)
                                 .with_rotation(Quat::from_rotation_y(rot)),
                             ..Default::default()
                         },
+                        tint,
                         CubeProcessor::new(color),
-                        CubeRouter(vec![Vec3::new(0.0, 1.0, 0.5), Vec3::new(0.0, 1.0, 0.0)]),
+                        CubeRouter::new(vec![Vec3::new(0.0, 1.0, 0.5), Vec3::new(0.0, 1.0, 0.0)]),
                         LevelEntity,
                     ));
                 }
@@ -293,6 +585,7 @@ fn level_spawn(layout: Vec<Vec<Tile>>, mut cmds: Commands, asset_server: Res<Ass
     cmds.insert_resource(level);
 }
 
+#[allow(clippy::too_many_arguments)]
 fn spawn_object(
     object: Object,
     i: usize,
@@ -301,9 +594,12 @@ fn spawn_object(
     level: &mut Level,
     cmds: &mut Commands,
     asset_server: &Res<AssetServer>,
+    palette: &Palette,
 ) {
-    match object {
-        Object::Empty => level.set_floor(i, j),
+    let shape = ObjectShape::new(&object);
+    let lock = object.lock();
+    let entity = match object.unlocked().clone() {
+        Object::Empty => return level.set_floor(i, j),
         Object::Belt(path) => {
             let mut bb = BeltBuilder::new();
             for c in path.chars() {
@@ -311,10 +607,193 @@ fn spawn_object(
                     'f' | 'F' => bb = bb.forward(asset_server),
                     'l' | 'L' => bb = bb.left(asset_server),
                     'r' | 'R' => bb = bb.right(asset_server),
-                    _ => panic!("Unknown direction"),
+                    _ => unreachable!("Belt directions are checked in level_parse"),
                 }
             }
-            bb.build((i, j).into(), pos, level, cmds);
+            bb.build((i, j).into(), pos, level, cmds)
+        }
+        Object::Splitter => build_splitter((i, j).into(), pos, level, cmds, asset_server),
+        Object::Merger => build_merger((i, j).into(), pos, level, cmds, asset_server),
+        Object::Painter(from, to) => {
+            let dis = (i, j).into();
+            build_painter(from, to, dis, pos, level, cmds, asset_server, palette)
+        }
+        Object::Crate(_) => {
+            let tiles = shape.as_ref().unwrap().tiles.clone();
+            let dis = (i, j).into();
+            build_crate(tiles, dis, pos, level, cmds, asset_server, palette)
+        }
+        Object::Locked(_, _) => unreachable!("Locks are removed by Object::unlocked"),
+    };
+    // Objects have already been validated by `level_parse`
+    cmds.entity(entity).insert(shape.unwrap());
+    if lock != Lock::Free {
+        // The block is inserted by the builders, so set its lock once it exists
+        cmds.entity(entity)
+            .add(move |id: Entity, world: &mut World| {
+                if let Some(mut block) = world.get_mut::<Block>(id) {
+                    block.lock = lock;
+                }
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(file: &LevelFile) -> Result<Vec<Vec<Tile>>, LevelLoadError> {
+        level_parse(file, &Palette::default())
+    }
+
+    fn level(layout: &[&str], objects: Vec<Object>) -> LevelFile {
+        LevelFile {
+            layout: layout.iter().map(|s| s.to_string()).collect(),
+            objects,
+            par: None,
+            objectives: vec![],
+            inputs: BTreeMap::new(),
+            outputs: BTreeMap::new(),
+            kicks: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            parse(&level(&["#o#", "#?#", "#i#"], vec![])).unwrap_err(),
+            LevelLoadError::UnknownTile {
+                row: 1,
+                column: 1,
+                tile: '?'
+            }
+        );
+        assert_eq!(
+            parse(&level(&["#o#", "#1#", "#i#"], vec![Object::Empty])).unwrap_err(),
+            LevelLoadError::MissingObject {
+                row: 1,
+                column: 1,
+                index: 1
+            }
+        );
+        assert_eq!(
+            parse(&level(
+                &["#o#", "#0#", "#i#"],
+                vec![Object::Belt("fx".into())]
+            ))
+            .unwrap_err(),
+            LevelLoadError::UnknownDirection {
+                row: 1,
+                column: 1,
+                direction: 'x'
+            }
+        );
+        assert_eq!(
+            parse(&level(
+                &["#o#", "#0#", "#i#"],
+                vec![Object::Belt("fff".into())]
+            ))
+            .unwrap_err(),
+            LevelLoadError::OutOfBounds { row: 1, column: 1 }
+        );
+        for tiles in [vec![], vec![(0, 0), (0, 2)], vec![(0, 0), (1, 0), (1, 0)]] {
+            assert_eq!(
+                parse(&level(
+                    &["#o##", "#0 #", "#  #", "#i##"],
+                    vec![Object::Crate(tiles)]
+                ))
+                .unwrap_err(),
+                LevelLoadError::InvalidCrate { row: 1, column: 1 }
+            );
         }
+        assert_eq!(
+            parse(&level(
+                &["#o##", "# 0#", "#  #", "#i##"],
+                vec![Object::Crate(vec![(0, 0), (0, -1), (0, -2)])]
+            ))
+            .unwrap_err(),
+            LevelLoadError::OutOfBounds { row: 1, column: 2 }
+        );
+        assert!(parse(&level(
+            &["#o##", "#0 #", "#  #", "#i##"],
+            vec![Object::Crate(vec![(0, 0), (1, 0), (1, 1)])]
+        ))
+        .is_ok());
+        let mut file = level(&["#o#", "# #", "#i#"], vec![]);
+        file.outputs.insert((1, 1), CubeColor::GREEN);
+        assert_eq!(
+            parse(&file).unwrap_err(),
+            LevelLoadError::MisplacedSettings { row: 1, column: 1 }
+        );
+        assert!(parse(&level(
+            &["#o#", "#0#", "#i#"],
+            vec![Object::Belt("f".into())]
+        ))
+        .is_ok());
+    }
+
+    #[test]
+    fn test_io_settings() {
+        let file: LevelFile = ron::from_str(
+            "(
+                layout: [\"#o#O#\", \"#   #\", \"#i#N#\"],
+                objects: [],
+                inputs: {
+                    (1, 2): (delay: 1.5, offset: 0.5, count: Some(4), colors: [Green, Yellow]),
+                    (3, 2): (count: Some(2)),
+                },
+                outputs: {(3, 0): Black},
+            )",
+        )
+        .unwrap();
+        let layout = parse(&file).unwrap();
+        let Tile::Input(_, settings) = &layout[2][1] else {
+            panic!("Expected an input");
+        };
+        assert_eq!(
+            *settings,
+            SpawnSettings {
+                delay: 1.5,
+                offset: 0.5,
+                count: Some(4),
+                colors: vec![CubeColor::GREEN, CubeColor::YELLOW],
+            }
+        );
+        // Settings without colors keep the color of the tile
+        let Tile::Input(_, settings) = &layout[2][3] else {
+            panic!("Expected an input");
+        };
+        assert_eq!(settings.delay, 2.0);
+        assert_eq!(settings.colors, vec![CubeColor::YELLOW]);
+        assert!(matches!(layout[0][1], Tile::Output(_, CubeColor::PURPLE)));
+        assert!(matches!(layout[0][3], Tile::Output(_, CubeColor::BLACK)));
+    }
+
+    #[test]
+    fn test_unknown_colors() {
+        let unknown = |row, column| LevelLoadError::UnknownColor {
+            row,
+            column,
+            name: "Gren".to_string(),
+        };
+        let mut file = level(&["#o#", "# #", "#i#"], vec![]);
+        file.inputs.insert(
+            (1, 2),
+            SpawnSettings {
+                colors: vec![CubeColor::PURPLE, CubeColor::new("Gren")],
+                ..SpawnSettings::new(CubeColor::PURPLE)
+            },
+        );
+        assert_eq!(parse(&file).unwrap_err(), unknown(2, 1));
+
+        let mut file = level(&["#o#", "# #", "#i#"], vec![]);
+        file.outputs.insert((1, 0), CubeColor::new("Gren"));
+        assert_eq!(parse(&file).unwrap_err(), unknown(0, 1));
+
+        let file = level(
+            &["#o#", "#0#", "#i#"],
+            vec![Object::Painter(CubeColor::PURPLE, CubeColor::new("Gren"))],
+        );
+        assert_eq!(parse(&file).unwrap_err(), unknown(1, 1));
     }
 }
@@ -4,20 +4,21 @@ use bevy::render::RenderPlugin;
 use bevy_easings::EasingsPlugin;
 use bevy_mod_picking::DefaultPickingPlugins;
 
-use camera::CameraMovePlugin;
-use cubes::CubePlugin;
-use game::GamePlugin;
-use levels::LevelManagerPlugin;
-use load::LoadPlugin;
-use ui::UIPlugin;
-
-mod camera;
-mod cubes;
-mod game;
-mod levels;
-mod load;
-mod objects;
-mod ui;
+use ludum_dare_54::camera::CameraMovePlugin;
+use ludum_dare_54::controls::ControlsPlugin;
+use ludum_dare_54::cubes::CubePlugin;
+use ludum_dare_54::editor::EditorPlugin;
+use ludum_dare_54::game::GamePlugin;
+use ludum_dare_54::levels::LevelManagerPlugin;
+use ludum_dare_54::load::LoadPlugin;
+use ludum_dare_54::objectives::ObjectivePlugin;
+use ludum_dare_54::palette::PalettePlugin;
+use ludum_dare_54::routing::RoutingPlugin;
+use ludum_dare_54::save::SavePlugin;
+use ludum_dare_54::score::ScorePlugin;
+use ludum_dare_54::select::LevelSelectPlugin;
+use ludum_dare_54::ui::UIPlugin;
+use ludum_dare_54::AppState;
 
 fn main() {
     let mut limits = WgpuLimits::downlevel_webgl2_defaults();
@@ -53,17 +54,16 @@ fn main() {
             CameraMovePlugin,
             GamePlugin,
             CubePlugin,
+            EditorPlugin,
             UIPlugin,
             LevelManagerPlugin,
             LoadPlugin,
+            RoutingPlugin,
+            ObjectivePlugin,
+            SavePlugin,
+            ScorePlugin,
+            LevelSelectPlugin,
         ))
+        .add_plugins((PalettePlugin, ControlsPlugin))
         .run();
 }
-
-#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash, States)]
-pub enum AppState {
-    #[default]
-    Loading,
-    Level,
-    Unloading,
-}
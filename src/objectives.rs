@@ -0,0 +1,275 @@
+//! Goals a level can set on top of connecting every input to its output.
+
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::cubes::{reset_simulation, CubeProcessor, Simulation};
+use crate::game::Level;
+use crate::levels::LevelEntity;
+use crate::load::{LevelFile, LoadLevel};
+use crate::score::format_time;
+use crate::ui::PANEL_COLOR;
+use crate::AppState;
+
+pub struct ObjectivePlugin;
+
+impl Plugin for ObjectivePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Objectives>()
+            .add_systems(
+                OnEnter(AppState::Level),
+                start_objectives.after(reset_simulation),
+            )
+            .add_systems(
+                Update,
+                (
+                    reset_objectives.run_if(resource_exists_and_changed::<Level>()),
+                    update_objectives,
+                    update_objectives_hud,
+                )
+                    .chain()
+                    .run_if(in_state(AppState::Level)),
+            );
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum Objective {
+    /// Deliver this many cubes to every output, within a time limit (in seconds) if there is one.
+    Deliver {
+        cubes: usize,
+        #[serde(default)]
+        seconds: Option<f32>,
+    },
+    /// Deliver cubes to every output at this average rate, over at least this many seconds.
+    Sustain { per_minute: f32, seconds: f32 },
+}
+
+impl Objective {
+    /// Check the objective, given the fewest cubes delivered to any output and the time taken.
+    // `Option::is_none_or` needs a newer toolchain than the one Bevy 0.11 supports
+    #[allow(clippy::unnecessary_map_or)]
+    pub fn is_met(&self, delivered: usize, elapsed: f32) -> bool {
+        match *self {
+            Objective::Deliver { cubes, seconds } => {
+                delivered >= cubes && seconds.map_or(true, |s| elapsed <= s)
+            }
+            Objective::Sustain {
+                per_minute,
+                seconds,
+            } => elapsed >= seconds && delivered as f32 / elapsed * 60.0 >= per_minute,
+        }
+    }
+
+    pub fn describe(&self, delivered: usize, elapsed: f32) -> String {
+        match *self {
+            Objective::Deliver {
+                cubes,
+                seconds: None,
+            } => format!(
+                "Deliver {} cubes to each output: {}/{}",
+                cubes, delivered, cubes
+            ),
+            Objective::Deliver {
+                cubes,
+                seconds: Some(seconds),
+            } => format!(
+                "Deliver {} cubes to each output in {}: {}/{}, {}",
+                cubes,
+                format_time(seconds),
+                delivered.min(cubes),
+                cubes,
+                format_time(elapsed.min(seconds))
+            ),
+            Objective::Sustain {
+                per_minute,
+                seconds,
+            } => format!(
+                "Deliver {} cubes/min to each output for {}: {:.1}/min, {}",
+                per_minute,
+                format_time(seconds),
+                if elapsed > 0.0 {
+                    delivered as f32 / elapsed * 60.0
+                } else {
+                    0.0
+                },
+                format_time(elapsed.min(seconds))
+            ),
+        }
+    }
+}
+
+/// The progress towards the objectives of the current level, in simulated time. All progress starts
+/// over whenever a block is moved, so the objectives are met by the final layout.
+#[derive(Resource, Default, Debug)]
+pub struct Objectives {
+    goals: Vec<Objective>,
+    met: Vec<bool>,
+    /// When the layout last changed.
+    since: f32,
+    /// The processor counts when the layout last changed.
+    baseline: HashMap<Entity, usize>,
+    /// The fewest cubes delivered to any output since the layout last changed.
+    delivered: usize,
+    elapsed: f32,
+}
+
+impl Objectives {
+    pub fn is_complete(&self) -> bool {
+        self.met.iter().all(|m| *m)
+    }
+
+    pub fn summary(&self) -> String {
+        self.goals
+            .iter()
+            .zip(self.met.iter())
+            .map(|(goal, met)| {
+                let check = if *met { "[x]" } else { "[ ]" };
+                format!("{} {}", check, goal.describe(self.delivered, self.elapsed))
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+#[derive(Component)]
+struct ObjectivesText;
+
+fn start_objectives(
+    mut objectives: ResMut<Objectives>,
+    load: Res<LoadLevel>,
+    levels: Res<Assets<LevelFile>>,
+    simulation: Res<Simulation>,
+    mut cmds: Commands,
+    asset_server: Res<AssetServer>,
+) {
+    let goals = levels
+        .get(&load.0)
+        .map(|l| l.objectives.clone())
+        .unwrap_or_default();
+    *objectives = Objectives {
+        met: vec![false; goals.len()],
+        goals,
+        since: simulation.elapsed(),
+        ..default()
+    };
+    if objectives.goals.is_empty() {
+        return;
+    }
+    cmds.spawn((
+        LevelEntity,
+        NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                top: Val::Px(10.0),
+                left: Val::Px(10.0),
+                padding: UiRect::all(Val::Px(10.0)),
+                ..default()
+            },
+            background_color: PANEL_COLOR.into(),
+            ..default()
+        },
+    ))
+    .with_children(|parent| {
+        parent.spawn((
+            ObjectivesText,
+            TextBundle::from_section(
+                objectives.summary(),
+                TextStyle {
+                    font: asset_server.load("fonts/Tourney-Medium.ttf"),
+                    font_size: 20.0,
+                    color: Color::BLACK,
+                },
+            ),
+        ));
+    });
+}
+
+fn reset_objectives(
+    mut objectives: ResMut<Objectives>,
+    processors: Query<(Entity, &CubeProcessor)>,
+    simulation: Res<Simulation>,
+) {
+    objectives.since = simulation.elapsed();
+    objectives.baseline = processors.iter().map(|(e, p)| (e, p.count())).collect();
+    objectives.met.iter_mut().for_each(|m| *m = false);
+}
+
+/// Runs before the victory check, so the last cube delivered can complete the level.
+pub fn update_objectives(
+    mut objectives: ResMut<Objectives>,
+    processors: Query<(Entity, &CubeProcessor)>,
+    simulation: Res<Simulation>,
+) {
+    if objectives.goals.is_empty() {
+        return;
+    }
+    let delivered = processors
+        .iter()
+        .map(|(e, p)| {
+            p.count()
+                .saturating_sub(objectives.baseline.get(&e).copied().unwrap_or_default())
+        })
+        .min()
+        .unwrap_or_default();
+    let elapsed = simulation.elapsed() - objectives.since;
+    let objectives = objectives.as_mut();
+    objectives.delivered = delivered;
+    objectives.elapsed = elapsed;
+    for (goal, met) in objectives.goals.iter().zip(objectives.met.iter_mut()) {
+        *met |= goal.is_met(delivered, elapsed);
+    }
+}
+
+fn update_objectives_hud(
+    objectives: Res<Objectives>,
+    mut query: Query<&mut Text, With<ObjectivesText>>,
+) {
+    for mut text in query.iter_mut() {
+        text.sections[0].value = objectives.summary();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_objectives() {
+        let deliver = Objective::Deliver {
+            cubes: 5,
+            seconds: Some(30.0),
+        };
+        assert!(!deliver.is_met(4, 20.0));
+        assert!(deliver.is_met(5, 20.0));
+        assert!(!deliver.is_met(5, 31.0));
+
+        let sustain = Objective::Sustain {
+            per_minute: 12.0,
+            seconds: 30.0,
+        };
+        assert!(!sustain.is_met(10, 20.0));
+        assert!(sustain.is_met(6, 30.0));
+        assert!(!sustain.is_met(5, 30.0));
+
+        let file: LevelFile = ron::from_str(
+            "(layout: [], objects: [], objectives: [Deliver(cubes: 3), Sustain(per_minute: 10.0, seconds: 60.0)])",
+        )
+        .unwrap();
+        assert_eq!(
+            file.objectives,
+            vec![
+                Objective::Deliver {
+                    cubes: 3,
+                    seconds: None
+                },
+                Objective::Sustain {
+                    per_minute: 10.0,
+                    seconds: 60.0
+                }
+            ]
+        );
+    }
+}
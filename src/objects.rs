@@ -1,8 +1,128 @@
 use bevy::prelude::*;
 
-use crate::cubes::CubeRouter;
+use crate::cubes::{CubeColor, CubePainter, CubeRouter};
 use crate::game::{Block, Dis2, Level, MakeSceneDraggable, Rotation};
 use crate::levels::LevelEntity;
+use crate::load::Object;
+use crate::palette::Palette;
+
+/// A connection between two neighbouring tiles, along which cubes are passed.
+pub type Edge = (Dis2, Dis2);
+
+/// The tiles of a belt path (relative to the first tile), without any scenes.
+#[derive(Clone, Debug)]
+pub struct BeltShape {
+    pub tiles: Vec<Dis2>,
+    /// The tile the last belt hands the cubes over to.
+    pub exit: Dis2,
+}
+
+impl BeltShape {
+    /// The tile cubes enter the first belt from.
+    pub const ENTRY: Dis2 = Dis2::Z;
+
+    pub fn new(path: &str) -> Option<Self> {
+        let mut tiles = Vec::new();
+        let mut dir = Rotation::D0;
+        let mut dis = Dis2::ZERO;
+        for c in path.chars() {
+            tiles.push(dis);
+            match c {
+                'f' | 'F' => {}
+                'l' | 'L' => dir = dir.left(),
+                'r' | 'R' => dir = dir.right(),
+                _ => return None,
+            }
+            dis += dir.as_discrete();
+        }
+        Some(Self { tiles, exit: dis })
+    }
+}
+
+/// The tiles of any object, and the edges where cubes enter and leave it (relative to the first
+/// tile).
+#[derive(Component, Clone, Debug)]
+pub struct ObjectShape {
+    pub tiles: Vec<Dis2>,
+    pub entries: Vec<Edge>,
+    pub exits: Vec<Edge>,
+    /// Repaints the cubes passing through.
+    pub painter: Option<CubePainter>,
+}
+
+impl ObjectShape {
+    pub fn new(object: &Object) -> Option<Self> {
+        match object {
+            Object::Empty => None,
+            Object::Locked(_, object) => Self::new(object),
+            Object::Belt(path) => {
+                let belt = BeltShape::new(path)?;
+                Some(Self {
+                    entries: vec![(BeltShape::ENTRY, Dis2::ZERO)],
+                    exits: belt
+                        .tiles
+                        .last()
+                        .map(|t| (*t, belt.exit))
+                        .into_iter()
+                        .collect(),
+                    tiles: belt.tiles,
+                    painter: None,
+                })
+            }
+            Object::Splitter => Some(Self {
+                tiles: vec![Dis2::ZERO],
+                entries: vec![(Dis2::Z, Dis2::ZERO)],
+                exits: vec![(Dis2::ZERO, Dis2::NEG_X), (Dis2::ZERO, Dis2::X)],
+                painter: None,
+            }),
+            Object::Merger => Some(Self {
+                tiles: vec![Dis2::ZERO],
+                entries: vec![(Dis2::NEG_X, Dis2::ZERO), (Dis2::X, Dis2::ZERO)],
+                exits: vec![(Dis2::ZERO, Dis2::NEG_Z)],
+                painter: None,
+            }),
+            Object::Painter(from, to) => Some(Self {
+                tiles: vec![Dis2::ZERO],
+                entries: vec![(Dis2::Z, Dis2::ZERO)],
+                exits: vec![(Dis2::ZERO, Dis2::NEG_Z)],
+                painter: Some(CubePainter {
+                    from: *from,
+                    to: *to,
+                }),
+            }),
+            Object::Crate(tiles) => Some(Self {
+                tiles: crate_tiles(tiles)?,
+                entries: vec![],
+                exits: vec![],
+                painter: None,
+            }),
+        }
+    }
+}
+
+/// The tiles of a crate, if they form a single piece that includes the tile the crate is placed
+/// at.
+pub fn crate_tiles(tiles: &[(isize, isize)]) -> Option<Vec<Dis2>> {
+    let tiles = tiles
+        .iter()
+        .map(|(x, z)| Dis2::new(*x, *z))
+        .collect::<Vec<_>>();
+    if !tiles.contains(&Dis2::ZERO) {
+        return None;
+    }
+    // Repeated tiles or tiles that can't be reached from the first one leave some tiles over
+    let mut reached = vec![Dis2::ZERO];
+    let mut i = 0;
+    while let Some(tile) = reached.get(i).copied() {
+        for d in [Dis2::X, Dis2::NEG_X, Dis2::Z, Dis2::NEG_Z] {
+            if tiles.contains(&(tile + d)) && !reached.contains(&(tile + d)) {
+                reached.push(tile + d);
+            }
+        }
+        i += 1;
+    }
+    (reached.len() == tiles.len()).then_some(tiles)
+}
 
 pub struct BeltBuilder {
     tiles: Vec<Dis2>,
@@ -13,6 +133,12 @@ pub struct BeltBuilder {
     dis: Dis2,
 }
 
+impl Default for BeltBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl BeltBuilder {
     pub fn new() -> Self {
         Self {
@@ -71,22 +197,173 @@ impl BeltBuilder {
         self
     }
 
-    pub fn build(mut self, dis: Dis2, pos: Vec3, level: &mut Level, cmds: &mut Commands) {
+    pub fn build(self, dis: Dis2, pos: Vec3, level: &mut Level, cmds: &mut Commands) -> Entity {
         let mut block = Block::new(level.next_index(), dis);
         block.tiles = self.tiles;
-        level.place_unchecked(&block);
-        self.route.iter_mut().for_each(|v| v.y += 1.0);
-        cmds.spawn((
-            SpatialBundle::from_transform(Transform::from_translation(pos)),
-            MakeSceneDraggable(None),
-            CubeRouter(self.route),
-            block,
-            LevelEntity,
-        ))
+        spawn_block(block, vec![self.route], self.scenes, pos, level, cmds)
+    }
+}
+
+/// Build a single tile splitter, which sends cubes coming from behind alternately left and right.
+pub fn build_splitter(
+    dis: Dis2,
+    pos: Vec3,
+    level: &mut Level,
+    cmds: &mut Commands,
+    asset_server: &Res<AssetServer>,
+) -> Entity {
+    let paths = vec![
+        vec![Vec3::Z * 0.5, Vec3::ZERO, Vec3::NEG_X * 0.5],
+        vec![Vec3::Z * 0.5, Vec3::ZERO, Vec3::X * 0.5],
+    ];
+    let scenes = vec![
+        junction_scene("models/beltL.glb#Scene0", Rotation::D0, asset_server),
+        junction_scene("models/beltR.glb#Scene0", Rotation::D0, asset_server),
+    ];
+    let block = Block::new(level.next_index(), dis);
+    spawn_block(block, paths, scenes, pos, level, cmds)
+}
+
+/// Build a single tile merger, which sends cubes coming from the left and right forwards.
+pub fn build_merger(
+    dis: Dis2,
+    pos: Vec3,
+    level: &mut Level,
+    cmds: &mut Commands,
+    asset_server: &Res<AssetServer>,
+) -> Entity {
+    let paths = vec![
+        vec![Vec3::NEG_X * 0.5, Vec3::ZERO, Vec3::NEG_Z * 0.5],
+        vec![Vec3::X * 0.5, Vec3::ZERO, Vec3::NEG_Z * 0.5],
+    ];
+    let scenes = vec![
+        junction_scene("models/beltL.glb#Scene0", Rotation::D270, asset_server),
+        junction_scene("models/beltR.glb#Scene0", Rotation::D90, asset_server),
+    ];
+    let block = Block::new(level.next_index(), dis);
+    spawn_block(block, paths, scenes, pos, level, cmds)
+}
+
+/// Build a single tile straight belt, which repaints cubes of one color to another.
+#[allow(clippy::too_many_arguments)]
+pub fn build_painter(
+    from: CubeColor,
+    to: CubeColor,
+    dis: Dis2,
+    pos: Vec3,
+    level: &mut Level,
+    cmds: &mut Commands,
+    asset_server: &Res<AssetServer>,
+    palette: &Palette,
+) -> Entity {
+    let paths = vec![vec![Vec3::Z * 0.5, Vec3::ZERO, Vec3::NEG_Z * 0.5]];
+    let scenes = vec![junction_scene(
+        "models/belt.glb#Scene0",
+        Rotation::D0,
+        asset_server,
+    )];
+    let block = Block::new(level.next_index(), dis);
+    let entity = spawn_block(block, paths, scenes, pos, level, cmds);
+    // Show the colors with small cubes on the side of the belt
+    cmds.entity(entity)
+        .insert(CubePainter { from, to })
         .with_children(|p| {
-            for scene in self.scenes.into_iter() {
-                p.spawn((MakeSceneDraggable(Some(p.parent_entity())), scene));
+            for (color, z) in [(from, 0.3), (to, -0.3)] {
+                let (scene, tint) = palette.cube_model(color, asset_server);
+                p.spawn((
+                    MakeSceneDraggable(Some(entity)),
+                    SceneBundle {
+                        scene,
+                        transform: Transform::from_xyz(0.4, 1.0, z).with_scale(Vec3::splat(0.3)),
+                        ..Default::default()
+                    },
+                    tint,
+                ));
             }
         });
+    entity
+}
+
+const CRATE_COLOR: Color = Color::rgb(0.55, 0.38, 0.2);
+
+/// Build a crate, which only takes up space, from a large cube with a smaller one stacked on top
+/// on each tile.
+#[allow(clippy::too_many_arguments)]
+pub fn build_crate(
+    tiles: Vec<Dis2>,
+    dis: Dis2,
+    pos: Vec3,
+    level: &mut Level,
+    cmds: &mut Commands,
+    asset_server: &Res<AssetServer>,
+    palette: &Palette,
+) -> Entity {
+    let mut block = Block::new(level.next_index(), dis);
+    block.tiles = tiles;
+    let offsets = block
+        .tiles
+        .iter()
+        .map(|t| {
+            let (x, z): (isize, isize) = (*t).into();
+            Vec3::new(x as f32, 0.0, z as f32)
+        })
+        .collect::<Vec<_>>();
+    let entity = spawn_block(block, vec![], vec![], pos, level, cmds);
+    cmds.entity(entity).with_children(|p| {
+        for (i, offset) in offsets.into_iter().enumerate() {
+            // The cube model is 0.4 wide and tall, standing on the floor
+            let stack = [
+                Transform::from_translation(offset).with_scale(Vec3::splat(2.2)),
+                Transform::from_translation(offset + Vec3::Y * 0.88)
+                    .with_rotation(Quat::from_rotation_y(0.3 * (i % 3) as f32 - 0.3))
+                    .with_scale(Vec3::splat(1.4)),
+            ];
+            for transform in stack {
+                let (scene, tint) = palette.tinted_cube(CRATE_COLOR, asset_server);
+                p.spawn((
+                    MakeSceneDraggable(Some(entity)),
+                    SceneBundle {
+                        scene,
+                        transform,
+                        ..Default::default()
+                    },
+                    tint,
+                ));
+            }
+        }
+    });
+    entity
+}
+
+fn junction_scene(model: &str, rotation: Rotation, asset_server: &Res<AssetServer>) -> SceneBundle {
+    SceneBundle {
+        scene: asset_server.load(model),
+        transform: Transform::from_rotation(Quat::from_rotation_y(rotation.as_radians())),
+        ..Default::default()
     }
 }
+
+fn spawn_block(
+    block: Block,
+    mut paths: Vec<Vec<Vec3>>,
+    scenes: Vec<SceneBundle>,
+    pos: Vec3,
+    level: &mut Level,
+    cmds: &mut Commands,
+) -> Entity {
+    level.place_unchecked(&block);
+    paths.iter_mut().flatten().for_each(|v| v.y += 1.0);
+    cmds.spawn((
+        SpatialBundle::from_transform(Transform::from_translation(pos)),
+        MakeSceneDraggable(None),
+        CubeRouter::with_paths(paths),
+        block,
+        LevelEntity,
+    ))
+    .with_children(|p| {
+        for scene in scenes.into_iter() {
+            p.spawn((MakeSceneDraggable(Some(p.parent_entity())), scene));
+        }
+    })
+    .id()
+}
@@ -0,0 +1,279 @@
+//! The cube colors, loaded from a palette file so that colors can be added without new models or
+//! code. Colors without models of their own use shared models, with one material tinted.
+
+use std::collections::HashMap;
+
+use bevy::asset::LoadState;
+use bevy::prelude::*;
+use bevy::reflect::{TypePath, TypeUuid};
+use bevy_common_assets::ron::RonAssetPlugin;
+use serde::Deserialize;
+
+use crate::cubes::CubeColor;
+
+pub struct PalettePlugin;
+
+impl Plugin for PalettePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(RonAssetPlugin::<Palette>::new(&["palette.ron"]))
+            .init_resource::<Palette>()
+            .add_systems(Startup, load_palette)
+            .add_systems(
+                PreUpdate,
+                update_palette.run_if(resource_changed::<Assets<Palette>>()),
+            )
+            .add_systems(Update, tint_scenes);
+    }
+}
+
+const PALETTE_PATH: &str = "colors.palette.ron";
+
+/// The color of cubes missing from the palette, in the UI.
+const MISSING_COLOR: Color = Color::GRAY;
+
+#[derive(Resource)]
+pub struct PaletteHandle(pub Handle<Palette>);
+
+/// A model shared by the colors without models of their own.
+#[derive(Deserialize, Clone, Debug)]
+pub struct SharedModel {
+    pub scene: String,
+    /// The material of the scene that is tinted to the color, as an asset path.
+    pub material: String,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct PaletteColor {
+    pub name: CubeColor,
+    /// The color shown in the UI, which the shared models are also tinted to.
+    pub color: (f32, f32, f32),
+    /// The layout tiles of the inputs and outputs of this color.
+    pub input: char,
+    pub output: char,
+    /// Models for this color, instead of the tinted shared models.
+    #[serde(default)]
+    pub cube: Option<String>,
+    #[serde(default)]
+    pub io: Option<String>,
+}
+
+/// The palette resource is a copy of the loaded palette file, or the built-in colors until the
+/// file has loaded.
+#[derive(Resource, Deserialize, TypeUuid, TypePath, Clone, Debug)]
+#[uuid = "5b0d3c1e-94f2-4d8e-a3c6-7e21b9f04d5a"]
+pub struct Palette {
+    pub cube: SharedModel,
+    pub io: SharedModel,
+    pub colors: Vec<PaletteColor>,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        let color = |name, color, input, output, suffix| PaletteColor {
+            name,
+            color,
+            input,
+            output,
+            cube: Some(format!("models/cube{}.glb#Scene0", suffix)),
+            io: Some(format!("models/inout{}.glb#Scene0", suffix)),
+        };
+        Self {
+            cube: SharedModel {
+                scene: "models/cubeG.glb#Scene0".to_string(),
+                material: "models/cubeG.glb#Material0".to_string(),
+            },
+            io: SharedModel {
+                scene: "models/inoutG.glb#Scene0".to_string(),
+                material: "models/inoutG.glb#Material1".to_string(),
+            },
+            colors: vec![
+                color(CubeColor::GREEN, (0.2, 0.7, 0.3), 'I', 'O', 'G'),
+                color(CubeColor::PURPLE, (0.6, 0.3, 0.8), 'i', 'o', 'P'),
+                color(CubeColor::YELLOW, (0.95, 0.8, 0.2), 'N', 'U', 'Y'),
+                color(CubeColor::BLACK, (0.1, 0.1, 0.1), 'n', 'u', 'B'),
+            ],
+        }
+    }
+}
+
+impl Palette {
+    pub fn get(&self, color: CubeColor) -> Option<&PaletteColor> {
+        self.colors.iter().find(|c| c.name == color)
+    }
+
+    /// The color of the inputs with this layout tile.
+    pub fn input(&self, tile: char) -> Option<CubeColor> {
+        self.colors.iter().find(|c| c.input == tile).map(|c| c.name)
+    }
+
+    /// The color of the outputs with this layout tile.
+    pub fn output(&self, tile: char) -> Option<CubeColor> {
+        self.colors
+            .iter()
+            .find(|c| c.output == tile)
+            .map(|c| c.name)
+    }
+
+    /// A flat color for the cube, for use in the UI.
+    pub fn ui_color(&self, color: CubeColor) -> Color {
+        self.get(color).map_or(MISSING_COLOR, |c| {
+            Color::rgb(c.color.0, c.color.1, c.color.2)
+        })
+    }
+
+    pub fn cube_model(
+        &self,
+        color: CubeColor,
+        asset_server: &AssetServer,
+    ) -> (Handle<Scene>, Tint) {
+        let own = self.get(color).and_then(|c| c.cube.as_deref());
+        self.model(&self.cube, own, color, asset_server)
+    }
+
+    pub fn io_model(&self, color: CubeColor, asset_server: &AssetServer) -> (Handle<Scene>, Tint) {
+        let own = self.get(color).and_then(|c| c.io.as_deref());
+        self.model(&self.io, own, color, asset_server)
+    }
+
+    /// The shared cube model tinted to any color, for scenery built from cubes.
+    pub fn tinted_cube(&self, color: Color, asset_server: &AssetServer) -> (Handle<Scene>, Tint) {
+        tinted(&self.cube, color, asset_server)
+    }
+
+    fn model(
+        &self,
+        shared: &SharedModel,
+        own: Option<&str>,
+        color: CubeColor,
+        asset_server: &AssetServer,
+    ) -> (Handle<Scene>, Tint) {
+        match own {
+            Some(scene) => (asset_server.load(scene), Tint(None)),
+            None => tinted(shared, self.ui_color(color), asset_server),
+        }
+    }
+}
+
+fn tinted(shared: &SharedModel, color: Color, asset_server: &AssetServer) -> (Handle<Scene>, Tint) {
+    (
+        asset_server.load(shared.scene.as_str()),
+        Tint(Some((asset_server.load(shared.material.as_str()), color))),
+    )
+}
+
+/// Tints a material of the scene on the same entity to a color, once the scene has spawned.
+#[derive(Component, Clone, Default, Debug, PartialEq)]
+pub struct Tint(Option<(Handle<StandardMaterial>, Color)>);
+
+/// The tinted copies of materials, by the material and the tint.
+#[derive(Default)]
+struct TintedMaterials(HashMap<(Handle<StandardMaterial>, u32), Handle<StandardMaterial>>);
+
+fn load_palette(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(PaletteHandle(asset_server.load(PALETTE_PATH)));
+}
+
+/// Whether the palette file has loaded, or failed to load and the built-in colors are used.
+pub fn palette_ready(
+    handle: Option<Res<PaletteHandle>>,
+    palettes: Res<Assets<Palette>>,
+    asset_server: Res<AssetServer>,
+) -> bool {
+    handle.is_some_and(|h| {
+        palettes.contains(&h.0) || asset_server.get_load_state(&h.0) == LoadState::Failed
+    })
+}
+
+pub fn update_palette(
+    handle: Res<PaletteHandle>,
+    palettes: Res<Assets<Palette>>,
+    mut palette: ResMut<Palette>,
+) {
+    if let Some(loaded) = palettes.get(&handle.0) {
+        *palette = loaded.clone();
+    }
+}
+
+fn tint_scenes(
+    added: Query<(Entity, &Handle<StandardMaterial>), Added<Handle<StandardMaterial>>>,
+    parents: Query<&Parent>,
+    tints: Query<&Tint>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut tinted: Local<TintedMaterials>,
+    mut cmds: Commands,
+) {
+    for (entity, material) in added.iter() {
+        // The closest tint applies, so a cube without a tint on a tinted object is left alone
+        let Some((base, color)) = parents
+            .iter_ancestors(entity)
+            .find_map(|e| tints.get(e).ok())
+            .and_then(|t| t.0.as_ref())
+        else {
+            continue;
+        };
+        if material != base {
+            continue;
+        }
+        let key = (base.clone(), color.as_rgba_u32());
+        let handle = match tinted.0.get(&key) {
+            Some(handle) => handle.clone(),
+            None => {
+                let Some(mut copy) = materials.get(base).cloned() else {
+                    continue;
+                };
+                // Keep the transparency and the brightness of any glow
+                let glow = copy
+                    .emissive
+                    .r()
+                    .max(copy.emissive.g())
+                    .max(copy.emissive.b());
+                copy.base_color = color.with_a(copy.base_color.a());
+                copy.emissive = *color * glow;
+                let handle = materials.add(copy);
+                tinted.0.insert(key, handle.clone());
+                handle
+            }
+        };
+        cmds.entity(entity).insert(handle);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::load::Object;
+
+    #[test]
+    fn test_palette_file() {
+        let palette: Palette =
+            ron::from_str(&std::fs::read_to_string("assets/colors.palette.ron").unwrap()).unwrap();
+        // The built-in colors are the start of the palette file
+        for (builtin, loaded) in Palette::default().colors.iter().zip(palette.colors.iter()) {
+            assert_eq!(builtin.name, loaded.name);
+            assert_eq!(
+                (builtin.input, builtin.output),
+                (loaded.input, loaded.output)
+            );
+        }
+        let tiles = palette
+            .colors
+            .iter()
+            .flat_map(|c| [c.input, c.output])
+            .collect::<Vec<_>>();
+        let mut unique = tiles.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(unique.len(), tiles.len(), "Two colors share a tile");
+        assert!(!tiles
+            .iter()
+            .any(|t| " _EL#".contains(*t) || t.is_ascii_digit()));
+        assert!(palette.colors.iter().any(|c| c.cube.is_none()));
+        assert_eq!(palette.input('a'), Some(CubeColor::new("Cyan")));
+
+        // Colors are written as bare names, like the built-in colors used to be
+        let painter = Object::Painter(CubeColor::GREEN, CubeColor::new("Cyan"));
+        let written = ron::to_string(&painter).unwrap();
+        assert_eq!(written, "Painter(Green,Cyan)");
+        assert_eq!(ron::from_str::<Object>(&written).unwrap(), painter);
+    }
+}
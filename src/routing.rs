@@ -0,0 +1,364 @@
+//! The routing graph: which inputs feed which outputs, worked out from the tiles of the placed
+//! pieces rather than from the positions of their scenes.
+
+use std::collections::HashSet;
+use std::f32::consts::PI;
+
+use bevy::prelude::*;
+use bevy_mod_picking::prelude::*;
+
+use crate::controls::Selected;
+use crate::cubes::{CubeColor, CubeRouter};
+use crate::game::{Block, Dis2, Level, Rotation};
+use crate::objects::{Edge, ObjectShape};
+use crate::palette::Palette;
+use crate::AppState;
+
+pub struct RoutingPlugin;
+
+impl Plugin for RoutingPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Routing>()
+            .init_resource::<RoutePreview>()
+            .add_systems(
+                Update,
+                (
+                    update_routing.run_if(resource_exists_and_changed::<Level>()),
+                    toggle_route_preview,
+                    draw_route_preview.run_if(resource_exists::<Level>()),
+                )
+                    .run_if(in_state(AppState::Level)),
+            );
+    }
+}
+
+/// The edge cubes leave an input through, for an input rotated as in `level_surround`.
+pub fn input_edge(rotation: f32, dis: Dis2) -> Edge {
+    let dir = Quat::from_rotation_y(rotation + PI * 0.5) * Vec3::NEG_Z;
+    (dis, dis + to_discrete(dir))
+}
+
+/// The edge cubes enter an output through, for an output rotated as in `level_surround`.
+pub fn output_edge(rotation: f32, dis: Dis2) -> Edge {
+    let dir = Quat::from_rotation_y(rotation) * Vec3::Z;
+    (dis + to_discrete(dir), dis)
+}
+
+fn to_discrete(dir: Vec3) -> Dis2 {
+    Dis2::new(dir.x.round() as isize, dir.z.round() as isize)
+}
+
+/// A piece placed in the level.
+pub type Piece<'a> = (Dis2, Rotation, &'a ObjectShape);
+
+#[derive(Resource, Clone, Debug, Default)]
+pub struct Routing {
+    /// The colors arriving at each output, in the same order as `Level::outputs`.
+    received: Vec<HashSet<CubeColor>>,
+    expected: Vec<CubeColor>,
+    /// The pieces cubes reach from the inputs, with the color they carry into each piece.
+    linked: Vec<(usize, CubeColor)>,
+    /// The edges where a path from an input ends without reaching an output.
+    broken: Vec<Edge>,
+    /// The pieces that pass cubes around in a loop.
+    cycle: Vec<usize>,
+    /// The block entity of each piece, in the game.
+    entities: Vec<Entity>,
+}
+
+impl Routing {
+    pub fn new(level: &Level, pieces: &[Piece]) -> Self {
+        let placed = pieces
+            .iter()
+            .map(|(pos, rot, shape)| {
+                let place =
+                    |(from, to): &Edge| (*pos + from.rotated(*rot), *pos + to.rotated(*rot));
+                (
+                    shape.entries.iter().map(place).collect::<Vec<_>>(),
+                    shape.exits.iter().map(place).collect::<Vec<_>>(),
+                    shape.painter,
+                )
+            })
+            .collect::<Vec<_>>();
+        let next = |edge: Edge| {
+            placed
+                .iter()
+                .enumerate()
+                .filter(move |(_, (entries, _, _))| entries.contains(&edge))
+                .map(|(i, _)| i)
+        };
+        let successors = |piece: usize| {
+            placed[piece]
+                .1
+                .iter()
+                .flat_map(|e| next(*e))
+                .collect::<Vec<_>>()
+        };
+
+        // Follow every path forwards from the inputs, keeping track of the cube color
+        let mut received = vec![HashSet::new(); level.outputs().len()];
+        let mut linked = vec![];
+        let mut broken = vec![];
+        let mut visited = HashSet::new();
+        let mut stack = level.inputs().to_vec();
+        while let Some((edge, color)) = stack.pop() {
+            if !visited.insert((edge, color)) {
+                continue;
+            }
+            let mut ends = true;
+            for (i, (e, _)) in level.outputs().iter().enumerate() {
+                if *e == edge {
+                    received[i].insert(color);
+                    ends = false;
+                }
+            }
+            for i in next(edge) {
+                ends = false;
+                if !linked.contains(&(i, color)) {
+                    linked.push((i, color));
+                }
+                let (_, exits, painter) = &placed[i];
+                let color = painter.map_or(color, |p| p.paint(color));
+                stack.extend(exits.iter().map(|e| (*e, color)));
+            }
+            if ends && !broken.contains(&edge) {
+                broken.push(edge);
+            }
+        }
+
+        // Look for a cycle among all pieces, whether or not any cubes reach it
+        let mut state = vec![Visit::New; placed.len()];
+        let mut cycle = vec![];
+        for start in 0..placed.len() {
+            if state[start] != Visit::New {
+                continue;
+            }
+            state[start] = Visit::Open;
+            let mut stack = vec![(start, successors(start))];
+            while let Some((piece, open)) = stack.last_mut() {
+                match open.pop() {
+                    Some(i) if state[i] == Visit::Open => {
+                        // The open pieces from `i` onwards are the ones around the loop
+                        let from = stack.iter().position(|(p, _)| *p == i).unwrap();
+                        for (p, _) in &stack[from..] {
+                            if !cycle.contains(p) {
+                                cycle.push(*p);
+                            }
+                        }
+                    }
+                    Some(i) if state[i] == Visit::New => {
+                        state[i] = Visit::Open;
+                        stack.push((i, successors(i)));
+                    }
+                    Some(_) => {}
+                    None => {
+                        state[*piece] = Visit::Done;
+                        stack.pop();
+                    }
+                }
+            }
+        }
+
+        Self {
+            received,
+            expected: level.outputs().iter().map(|(_, c)| *c).collect(),
+            linked,
+            broken,
+            cycle,
+            entities: vec![],
+        }
+    }
+
+    /// Check whether an input of the given color feeds the output, after any repainting.
+    pub fn is_connected(&self, output: usize, color: CubeColor) -> bool {
+        self.received
+            .get(output)
+            .is_some_and(|colors| colors.contains(&color))
+    }
+
+    /// Check that every output is fed by inputs, and only receives cubes of its own color.
+    pub fn is_solved(&self) -> bool {
+        self.received
+            .iter()
+            .zip(self.expected.iter())
+            .all(|(colors, color)| !colors.is_empty() && colors.iter().all(|c| c == color))
+    }
+
+    /// The pieces (as indices into the pieces the routing was built from) that cubes reach, and the
+    /// colors they reach them with.
+    pub fn linked(&self) -> &[(usize, CubeColor)] {
+        &self.linked
+    }
+
+    /// The edges where cubes would fall off the end of a path.
+    pub fn broken(&self) -> &[Edge] {
+        &self.broken
+    }
+
+    /// Whether the pieces pass cubes around in a loop.
+    pub fn has_cycle(&self) -> bool {
+        !self.cycle.is_empty()
+    }
+
+    /// The pieces (as indices into the pieces the routing was built from) on a loop.
+    pub fn cycle(&self) -> &[usize] {
+        &self.cycle
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Visit {
+    New,
+    Open,
+    Done,
+}
+
+/// Whether the routes are drawn over the level, which they are while a block is being moved: dragged
+/// with the mouse, or selected to move with the keyboard or a gamepad.
+#[derive(Resource, Default)]
+struct RoutePreview(bool);
+
+const BROKEN_COLOR: Color = Color::RED;
+
+fn update_routing(
+    level: Res<Level>,
+    blocks: Query<(Entity, &Block, &ObjectShape)>,
+    mut routing: ResMut<Routing>,
+) {
+    let pieces = blocks
+        .iter()
+        .map(|(_, b, s)| (b.position(), b.rotation(), s))
+        .collect::<Vec<_>>();
+    *routing = Routing::new(&level, &pieces);
+    routing.entities = blocks.iter().map(|(e, _, _)| e).collect();
+}
+
+fn toggle_route_preview(
+    mut start: EventReader<Pointer<DragStart>>,
+    mut end: EventReader<Pointer<DragEnd>>,
+    selected: Res<Selected>,
+    mut dragging: Local<bool>,
+    mut preview: ResMut<RoutePreview>,
+) {
+    if start.iter().count() > 0 {
+        *dragging = true;
+    }
+    if end.iter().count() > 0 {
+        *dragging = false;
+    }
+    preview.0 = *dragging || selected.0.is_some();
+}
+
+fn draw_route_preview(
+    preview: Res<RoutePreview>,
+    routing: Res<Routing>,
+    routers: Query<(&CubeRouter, &GlobalTransform)>,
+    blocks: Query<&Block>,
+    level: Res<Level>,
+    palette: Res<Palette>,
+    mut gizmos: Gizmos,
+) {
+    if !preview.0 {
+        return;
+    }
+    for (piece, color) in routing.linked() {
+        let Some(Ok((router, global))) = routing.entities.get(*piece).map(|e| routers.get(*e))
+        else {
+            continue;
+        };
+        for path in router.paths() {
+            gizmos.linestrip(
+                path.iter()
+                    .map(|p| global.transform_point(*p) + Vec3::Y * 0.1),
+                palette.ui_color(*color),
+            );
+        }
+    }
+    // Mark the broken ends, and every tile of the pieces on a loop, with a cross
+    for (from, to) in routing.broken() {
+        let pos = (level.to_vec3(*from) + level.to_vec3(*to)) * 0.5 + Vec3::Y * 1.1;
+        draw_cross(&mut gizmos, pos);
+    }
+    for piece in routing.cycle() {
+        let Some(Ok(block)) = routing.entities.get(*piece).map(|e| blocks.get(*e)) else {
+            continue;
+        };
+        for tile in block.iter() {
+            draw_cross(&mut gizmos, level.to_vec3(tile) + Vec3::Y * 1.1);
+        }
+    }
+}
+
+fn draw_cross(gizmos: &mut Gizmos, pos: Vec3) {
+    gizmos.line(
+        pos + Vec3::new(-0.2, 0.0, -0.2),
+        pos + Vec3::new(0.2, 0.0, 0.2),
+        BROKEN_COLOR,
+    );
+    gizmos.line(
+        pos + Vec3::new(-0.2, 0.0, 0.2),
+        pos + Vec3::new(0.2, 0.0, -0.2),
+        BROKEN_COLOR,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::load::Object;
+
+    fn belt(path: &str) -> ObjectShape {
+        ObjectShape::new(&Object::Belt(path.to_string())).unwrap()
+    }
+
+    #[test]
+    fn test_routing() {
+        let mut level = Level::new(3, 5);
+        level.add_input(input_edge(-PI * 0.5, Dis2::new(1, 4)), CubeColor::GREEN);
+        level.add_output(output_edge(0.0, Dis2::new(1, 0)), CubeColor::GREEN);
+
+        let straight = belt("fff");
+        let routing = Routing::new(&level, &[(Dis2::new(1, 3), Rotation::D0, &straight)]);
+        assert!(routing.is_solved());
+        assert!(routing.is_connected(0, CubeColor::GREEN));
+        assert!(!routing.is_connected(0, CubeColor::PURPLE));
+        assert!(!routing.has_cycle());
+
+        let routing = Routing::new(&level, &[(Dis2::new(1, 2), Rotation::D0, &straight)]);
+        assert!(!routing.is_solved());
+        assert!(!routing.is_connected(0, CubeColor::GREEN));
+        assert_eq!(routing.linked(), &[]);
+        assert_eq!(routing.broken(), &[(Dis2::new(1, 4), Dis2::new(1, 3))]);
+
+        let short = belt("ff");
+        let routing = Routing::new(&level, &[(Dis2::new(1, 3), Rotation::D0, &short)]);
+        assert_eq!(routing.linked(), &[(0, CubeColor::GREEN)]);
+        assert_eq!(routing.broken(), &[(Dis2::new(1, 2), Dis2::new(1, 1))]);
+    }
+
+    #[test]
+    fn test_cycle() {
+        let level = Level::new(4, 4);
+        let square = belt("rrrr");
+        assert!(Routing::new(&level, &[(Dis2::new(1, 1), Rotation::D0, &square)]).has_cycle());
+
+        // Two belts feeding each other
+        let half = belt("rr");
+        let pieces = [
+            (Dis2::new(1, 1), Rotation::D0, &half),
+            (Dis2::new(2, 2), Rotation::D180, &half),
+        ];
+        assert_eq!(Routing::new(&level, &pieces).cycle(), &[0, 1]);
+
+        // A belt feeding into the loop is not part of it
+        let pieces = [
+            (Dis2::new(1, 1), Rotation::D0, &half),
+            (Dis2::new(2, 2), Rotation::D180, &half),
+            (Dis2::new(0, 1), Rotation::D0, &belt("f")),
+        ];
+        assert_eq!(Routing::new(&level, &pieces).cycle(), &[0, 1]);
+
+        let turn = belt("rrr");
+        assert!(!Routing::new(&level, &[(Dis2::new(1, 1), Rotation::D0, &turn)]).has_cycle());
+    }
+}
@@ -0,0 +1,176 @@
+use std::collections::HashMap;
+
+use bevy::app::AppExit;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::game::{Block, Dis2, Level, Rotation};
+use crate::levels::{Campaign, CampaignFile, LevelState};
+use crate::score::{start_score, Score};
+use crate::AppState;
+
+pub struct SavePlugin;
+
+impl Plugin for SavePlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(SaveFile::read())
+            .add_systems(
+                OnEnter(AppState::Level),
+                restore_layout
+                    .after(start_score)
+                    .run_if(in_state(LevelState::Campaign)),
+            )
+            .add_systems(
+                Update,
+                save_layout
+                    .run_if(in_state(AppState::Level).and_then(in_state(LevelState::Campaign))),
+            )
+            .add_systems(OnEnter(AppState::Unloading), write_save)
+            .add_systems(Last, write_save.run_if(on_event::<AppExit>()));
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+const SAVE_PATH: &str = "conveyor_chaos_save.ron";
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SavedBlock {
+    pub index: usize,
+    pub position: Dis2,
+    pub rotation: Rotation,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct PersonalBest {
+    pub moves: usize,
+    pub time: f32,
+}
+
+#[derive(Resource, Serialize, Deserialize, Default, Debug)]
+pub struct SaveFile {
+    /// The first campaign level that has not been completed.
+    pub progress: usize,
+    /// In-progress block layouts, keyed by level path.
+    pub layouts: HashMap<String, Vec<SavedBlock>>,
+    /// The fewest moves and fastest time for each completed level, keyed by level path.
+    #[serde(default)]
+    pub bests: HashMap<String, PersonalBest>,
+}
+
+impl SaveFile {
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn read() -> Self {
+        std::fs::read_to_string(SAVE_PATH)
+            .ok()
+            .and_then(|s| ron::from_str(&s).ok())
+            .unwrap_or_default()
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn read() -> Self {
+        Self::default()
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn write(&self) {
+        match ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default()) {
+            Ok(s) => {
+                if let Err(e) = std::fs::write(SAVE_PATH, s) {
+                    warn!("Could not write save file: {}", e);
+                }
+            }
+            Err(e) => warn!("Could not serialize save file: {}", e),
+        }
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn write(&self) {}
+
+    /// Mark a level as completed, discarding its in-progress layout.
+    pub fn complete(&mut self, level: usize, path: &str) {
+        self.progress = self.progress.max(level + 1);
+        self.layouts.remove(path);
+        self.write();
+    }
+
+    /// Keep the fewest moves and the fastest time, which need not come from the same attempt.
+    pub fn record_best(&mut self, path: &str, score: PersonalBest) {
+        let best = self.bests.entry(path.to_string()).or_insert(score);
+        best.moves = best.moves.min(score.moves);
+        best.time = best.time.min(score.time);
+        self.write();
+    }
+}
+
+/// Keep the layout up to date in memory, it's written to disk when leaving the level.
+fn save_layout(
+    changed: Query<(), Changed<Block>>,
+    blocks: Query<&Block>,
+    campaign: Res<Campaign>,
+    campaigns: Res<Assets<CampaignFile>>,
+    mut save: ResMut<SaveFile>,
+) {
+    if changed.is_empty() {
+        return;
+    }
+    if let Some(level) = campaign.current(&campaigns) {
+        let mut layout = blocks
+            .iter()
+            .map(|b| SavedBlock {
+                index: b.index,
+                position: b.position(),
+                rotation: b.rotation(),
+            })
+            .collect::<Vec<_>>();
+        layout.sort_by_key(|b| b.index);
+        save.layouts.insert(level.path.clone(), layout);
+    }
+}
+
+fn write_save(save: Res<SaveFile>) {
+    save.write();
+}
+
+fn restore_layout(
+    mut blocks: Query<(&mut Block, &mut Transform)>,
+    mut level: ResMut<Level>,
+    campaign: Res<Campaign>,
+    campaigns: Res<Assets<CampaignFile>>,
+    save: Res<SaveFile>,
+    mut score: ResMut<Score>,
+) {
+    let Some(layout) = campaign
+        .current(&campaigns)
+        .and_then(|l| save.layouts.get(&l.path))
+    else {
+        return;
+    };
+    if layout.len() != blocks.iter().len() {
+        return;
+    }
+    // Check the whole layout before moving anything, in case the level has changed since saving
+    let mut restored = level.clone();
+    blocks.iter().for_each(|(b, _)| restored.remove(b));
+    for (block, _) in blocks.iter() {
+        let Some(saved) = layout.iter().find(|s| s.index == block.index) else {
+            return;
+        };
+        let from = (block.position(), block.rotation());
+        if !block.lock.allows(from, (saved.position, saved.rotation))
+            || !restored.try_place(block, saved.position, saved.rotation)
+        {
+            return;
+        }
+        let mut block = block.clone();
+        block.translate(saved.position).rotate(saved.rotation);
+        restored.place(&block);
+    }
+    for (mut block, mut transform) in blocks.iter_mut() {
+        let saved = layout.iter().find(|s| s.index == block.index).unwrap();
+        block.translate(saved.position).rotate(saved.rotation);
+        transform.translation = restored.to_vec3(saved.position);
+        transform.rotation = Quat::from_rotation_y(saved.rotation.as_radians());
+    }
+    *level = restored;
+    score.resume();
+}
@@ -0,0 +1,201 @@
+use bevy::prelude::*;
+
+use crate::levels::{Campaign, CampaignFile, LevelState};
+use crate::load::{LevelFile, LoadLevel};
+use crate::save::{PersonalBest, SaveFile};
+use crate::AppState;
+
+pub struct ScorePlugin;
+
+impl Plugin for ScorePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Score>()
+            .add_systems(OnEnter(AppState::Level), start_score)
+            .add_systems(
+                Update,
+                save_best.run_if(in_state(AppState::Level).and_then(resource_changed::<Score>())),
+            );
+    }
+}
+
+#[derive(Resource, Default, Debug)]
+pub struct Score {
+    moves: usize,
+    start: f32,
+    time: Option<f32>,
+    par: Option<usize>,
+    /// The level path personal bests are saved under, if they are saved at all.
+    path: Option<String>,
+    best: Option<PersonalBest>,
+    recorded: bool,
+    /// Whether the run carries on from a saved layout. The moves and time before it was saved are
+    /// unknown, so the run can't set a personal best.
+    resumed: bool,
+}
+
+impl Score {
+    /// Count a drag or rotation, unless the level has already been completed.
+    pub fn count_move(&mut self) {
+        if self.time.is_none() {
+            self.moves += 1;
+        }
+    }
+
+    pub fn resume(&mut self) {
+        self.resumed = true;
+    }
+
+    /// Stop the timer, the first time the level is completed.
+    pub fn finish(&mut self, now: f32) {
+        if self.time.is_none() {
+            self.time = Some(now - self.start);
+        }
+    }
+
+    pub fn summary(&self) -> String {
+        let mut lines = vec![match self.par {
+            Some(par) => format!("Moves: {} (par {})", self.moves, par),
+            None => format!("Moves: {}", self.moves),
+        }];
+        if let Some(time) = self.time {
+            lines.push(format!("Time: {}", format_time(time)));
+            match &self.best {
+                Some(best) => {
+                    lines.push(format!(
+                        "Best: {} moves, {}",
+                        best.moves,
+                        format_time(best.time)
+                    ));
+                    if !self.resumed && (self.moves < best.moves || time < best.time) {
+                        lines.push("New personal best!".to_string());
+                    }
+                }
+                // The first completion of a level that keeps bests is always a new one
+                None if self.path.is_some() && !self.resumed => {
+                    lines.push("New personal best!".to_string())
+                }
+                None => {}
+            }
+            if self.resumed && self.path.is_some() {
+                lines.push("Resumed runs don't count as personal bests".to_string());
+            }
+        }
+        lines.join("\n")
+    }
+}
+
+pub fn format_time(seconds: f32) -> String {
+    // Round before splitting, so 59.97 seconds becomes 1:00.0 rather than 0:60.0
+    let t = (seconds * 10.0).round() / 10.0;
+    format!("{}:{:04.1}", (t / 60.0) as u32, t % 60.0)
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn start_score(
+    mut score: ResMut<Score>,
+    time: Res<Time>,
+    load: Res<LoadLevel>,
+    levels: Res<Assets<LevelFile>>,
+    level_state: Res<State<LevelState>>,
+    campaign: Res<Campaign>,
+    campaigns: Res<Assets<CampaignFile>>,
+    save: Res<SaveFile>,
+) {
+    let path = match **level_state {
+        LevelState::Campaign => campaign.current(&campaigns).map(|l| l.path.clone()),
+        _ => None,
+    };
+    *score = Score {
+        start: time.elapsed_seconds(),
+        par: levels.get(&load.0).and_then(|l| l.par),
+        best: path.as_ref().and_then(|p| save.bests.get(p)).copied(),
+        path,
+        ..default()
+    };
+}
+
+fn save_best(mut score: ResMut<Score>, mut save: ResMut<SaveFile>) {
+    if score.recorded || score.resumed {
+        return;
+    }
+    if let (Some(time), Some(path)) = (score.time, &score.path) {
+        save.record_best(
+            path,
+            PersonalBest {
+                moves: score.moves,
+                time,
+            },
+        );
+        score.recorded = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary() {
+        let mut score = Score {
+            par: Some(5),
+            best: Some(PersonalBest {
+                moves: 4,
+                time: 90.0,
+            }),
+            ..default()
+        };
+        score.count_move();
+        score.count_move();
+        score.finish(75.3);
+        score.count_move();
+        assert_eq!(
+            score.summary(),
+            "Moves: 2 (par 5)\nTime: 1:15.3\nBest: 4 moves, 1:30.0\nNew personal best!"
+        );
+    }
+
+    #[test]
+    fn test_resumed() {
+        let mut score = Score {
+            path: Some("levels/level_00.ron".to_string()),
+            best: Some(PersonalBest {
+                moves: 4,
+                time: 90.0,
+            }),
+            ..default()
+        };
+        score.resume();
+        score.count_move();
+        score.finish(12.0);
+        assert_eq!(
+            score.summary(),
+            "Moves: 1\nTime: 0:12.0\nBest: 4 moves, 1:30.0\nResumed runs don't count as personal bests"
+        );
+    }
+
+    #[test]
+    fn test_format_time() {
+        assert_eq!(format_time(75.3), "1:15.3");
+        assert_eq!(format_time(59.97), "1:00.0");
+        assert_eq!(format_time(119.96), "2:00.0");
+    }
+
+    #[test]
+    fn test_first_best() {
+        let mut score = Score {
+            path: Some("levels/level_00.ron".to_string()),
+            ..default()
+        };
+        score.count_move();
+        score.finish(12.0);
+        assert_eq!(
+            score.summary(),
+            "Moves: 1\nTime: 0:12.0\nNew personal best!"
+        );
+
+        // Levels without saved bests, like random puzzles, never have one
+        let mut score = Score::default();
+        score.finish(12.0);
+        assert_eq!(score.summary(), "Moves: 0\nTime: 0:12.0");
+    }
+}
@@ -0,0 +1,273 @@
+use bevy::prelude::*;
+use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+use bevy::render::texture::ImageSampler;
+
+use crate::levels::{Campaign, CampaignFile, LeaveLevel, LevelEntity, LevelState};
+use crate::load::{level_parse, LevelFile, Object, Tile};
+use crate::objects::ObjectShape;
+use crate::palette::Palette;
+use crate::save::SaveFile;
+use crate::score::format_time;
+use crate::ui::{spawn_button, DespawnButton, PANEL_COLOR};
+use crate::AppState;
+
+pub struct LevelSelectPlugin;
+
+impl Plugin for LevelSelectPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (open_level_select, select_level, draw_thumbnails)
+                .run_if(in_state(AppState::Level).and_then(in_state(LevelState::MainMenu))),
+        );
+    }
+}
+
+#[derive(Component)]
+pub struct LevelSelectButton;
+
+#[derive(Component)]
+struct SelectLevel(usize);
+
+/// A placeholder for a level thumbnail, replaced with the image once the level has loaded.
+#[derive(Component)]
+struct LevelThumbnail(Handle<LevelFile>);
+
+/// The size of a tile in the thumbnails, in pixels.
+const THUMBNAIL_SCALE: f32 = 6.0;
+const LOCKED_COLOR: Color = Color::rgba(0.5, 0.5, 0.5, 0.5);
+
+fn open_level_select(
+    interaction_query: Query<&Interaction, (Changed<Interaction>, With<LevelSelectButton>)>,
+    mut cmds: Commands,
+    asset_server: Res<AssetServer>,
+    campaign: Res<Campaign>,
+    campaigns: Res<Assets<CampaignFile>>,
+    save: Res<SaveFile>,
+) {
+    if !interaction_query
+        .iter()
+        .any(|i| matches!(i, Interaction::Pressed))
+    {
+        return;
+    }
+    let Some(file) = campaigns.get(&campaign.file) else {
+        return;
+    };
+    let font = asset_server.load("fonts/Tourney-Medium.ttf");
+    cmds.spawn((
+        LevelEntity,
+        NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                align_items: AlignItems::Center,
+                justify_content: JustifyContent::Center,
+                ..default()
+            },
+            ..default()
+        },
+    ))
+    .with_children(|p| {
+        let parent_id = p.parent_entity();
+        p.spawn(NodeBundle {
+            style: Style {
+                width: Val::Percent(80.0),
+                align_items: AlignItems::Center,
+                flex_direction: FlexDirection::Column,
+                row_gap: Val::Px(10.0),
+                padding: UiRect::all(Val::Px(10.0)),
+                ..default()
+            },
+            background_color: PANEL_COLOR.into(),
+            ..default()
+        })
+        .with_children(|parent| {
+            parent.spawn(TextBundle::from_section(
+                "Select level",
+                TextStyle {
+                    font: font.clone(),
+                    font_size: 48.0,
+                    color: Color::BLACK,
+                },
+            ));
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        flex_wrap: FlexWrap::Wrap,
+                        justify_content: JustifyContent::Center,
+                        row_gap: Val::Px(10.0),
+                        column_gap: Val::Px(10.0),
+                        ..default()
+                    },
+                    ..default()
+                })
+                .with_children(|grid| {
+                    for (i, level) in file.levels.iter().enumerate() {
+                        let unlocked = i <= save.progress;
+                        let status = if !unlocked {
+                            "Locked".to_string()
+                        } else if let Some(best) = save.bests.get(&level.path) {
+                            format!("Best: {} moves, {}", best.moves, format_time(best.time))
+                        } else if i < save.progress {
+                            "Completed".to_string()
+                        } else {
+                            "Not completed".to_string()
+                        };
+                        let style = Style {
+                            width: Val::Px(200.0),
+                            align_items: AlignItems::Center,
+                            flex_direction: FlexDirection::Column,
+                            row_gap: Val::Px(4.0),
+                            padding: UiRect::all(Val::Px(6.0)),
+                            border: UiRect::all(Val::Px(3.0)),
+                            ..default()
+                        };
+                        let mut card = if unlocked {
+                            grid.spawn((
+                                SelectLevel(i),
+                                ButtonBundle {
+                                    style,
+                                    border_color: Color::BLACK.into(),
+                                    background_color: Color::WHITE.into(),
+                                    ..default()
+                                },
+                            ))
+                        } else {
+                            grid.spawn(NodeBundle {
+                                style,
+                                border_color: Color::BLACK.into(),
+                                background_color: LOCKED_COLOR.into(),
+                                ..default()
+                            })
+                        };
+                        card.with_children(|card| {
+                            card.spawn((
+                                LevelThumbnail(asset_server.load(&level.path)),
+                                ImageBundle {
+                                    background_color: Color::NONE.into(),
+                                    ..default()
+                                },
+                            ));
+                            card.spawn(TextBundle::from_section(
+                                &level.title,
+                                TextStyle {
+                                    font: font.clone(),
+                                    font_size: 24.0,
+                                    color: Color::BLACK,
+                                },
+                            ));
+                            card.spawn(TextBundle::from_section(
+                                status,
+                                TextStyle {
+                                    font: font.clone(),
+                                    font_size: 16.0,
+                                    color: Color::BLACK,
+                                },
+                            ));
+                        });
+                    }
+                });
+            spawn_button(parent, "Back", 200.0, &asset_server).insert(DespawnButton(parent_id));
+        });
+    });
+}
+
+fn select_level(
+    interaction_query: Query<(&Interaction, &SelectLevel), Changed<Interaction>>,
+    mut campaign: ResMut<Campaign>,
+    mut leave: EventWriter<LeaveLevel>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    for (interaction, select) in interaction_query.iter() {
+        if matches!(interaction, Interaction::Pressed) {
+            campaign.level = select.0;
+            leave.send(LeaveLevel(LevelState::Campaign));
+            next_state.set(AppState::Unloading);
+            return;
+        }
+    }
+}
+
+fn draw_thumbnails(
+    mut query: Query<(
+        Entity,
+        &LevelThumbnail,
+        &mut UiImage,
+        &mut Style,
+        &mut BackgroundColor,
+    )>,
+    levels: Res<Assets<LevelFile>>,
+    palette: Res<Palette>,
+    mut images: ResMut<Assets<Image>>,
+    mut cmds: Commands,
+) {
+    for (entity, thumbnail, mut image, mut style, mut color) in query.iter_mut() {
+        let Some(file) = levels.get(&thumbnail.0) else {
+            continue;
+        };
+        cmds.entity(entity).remove::<LevelThumbnail>();
+        let Some(thumbnail) = thumbnail_image(file, &palette) else {
+            continue;
+        };
+        style.width = Val::Px(thumbnail.size().x * THUMBNAIL_SCALE);
+        style.height = Val::Px(thumbnail.size().y * THUMBNAIL_SCALE);
+        image.texture = images.add(thumbnail);
+        *color = Color::WHITE.into();
+    }
+}
+
+/// Draw a level with one pixel per tile.
+fn thumbnail_image(file: &LevelFile, palette: &Palette) -> Option<Image> {
+    const FLOOR: Color = Color::rgb(0.85, 0.85, 0.8);
+    const WALL: Color = Color::rgb(0.3, 0.3, 0.35);
+    const BELT: Color = Color::rgb(0.9, 0.5, 0.1);
+    const CRATE: Color = Color::rgb(0.55, 0.38, 0.2);
+
+    let layout = level_parse(file, palette).ok()?;
+    let width = layout.iter().map(|row| row.len()).max()?;
+    let height = layout.len();
+    let mut pixels = vec![Color::NONE; width * height];
+    for (j, row) in layout.iter().enumerate() {
+        for (i, tile) in row.iter().enumerate() {
+            pixels[j * width + i] = match tile {
+                Tile::Empty => Color::NONE,
+                Tile::Wall(_, _, _, _) | Tile::Door(_, _, _, _, _) => WALL,
+                Tile::Floor(_) | Tile::Loadingbay => FLOOR,
+                Tile::Input(_, settings) => palette.ui_color(settings.colors[0]),
+                Tile::Output(_, color) => palette.ui_color(*color),
+            };
+        }
+    }
+    for (j, row) in layout.iter().enumerate() {
+        for (i, tile) in row.iter().enumerate() {
+            let Tile::Floor(object) = tile else {
+                continue;
+            };
+            let color = match object.unlocked() {
+                Object::Crate(_) => CRATE,
+                _ => BELT,
+            };
+            if let Some(shape) = ObjectShape::new(object) {
+                for d in shape.tiles {
+                    let (x, z): (isize, isize) = d.into();
+                    pixels[(j as isize + z) as usize * width + (i as isize + x) as usize] = color;
+                }
+            }
+        }
+    }
+
+    let mut image = Image::new(
+        Extent3d {
+            width: width as u32,
+            height: height as u32,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        pixels.iter().flat_map(|c| c.as_rgba_u8()).collect(),
+        TextureFormat::Rgba8UnormSrgb,
+    );
+    image.sampler_descriptor = ImageSampler::nearest();
+    Some(image)
+}
@@ -0,0 +1,625 @@
+//! A headless solver for level files, so levels can be validated without a Bevy `App`.
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, VecDeque};
+
+use crate::cubes::CubeColor;
+use crate::game::{Block, Dis2, Level, Lock, Rotation, Turn};
+use crate::load::{level_parse, level_surround, LevelFile, LevelLoadError, Object, Tile};
+use crate::objects::{Edge, ObjectShape};
+use crate::palette::Palette;
+use crate::routing::{input_edge, output_edge, Routing};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Placement {
+    pub index: usize,
+    pub position: Dis2,
+    pub rotation: Rotation,
+}
+
+#[derive(Debug, Clone)]
+pub struct Solution {
+    pub placements: Vec<Placement>,
+    /// The moves it takes to get there, which may be more than the fewest possible.
+    pub moves: usize,
+}
+
+#[derive(Debug, Clone)]
+pub enum SolverResult {
+    Solved(Solution),
+    /// The blocks can't route every input, or every reachable arrangement has been explored
+    /// without finding a solution.
+    Unsolvable {
+        explored: usize,
+    },
+    /// The search was stopped before it could find a solution or exhaust all arrangements.
+    GaveUp {
+        explored: usize,
+    },
+}
+
+pub fn solve(
+    level: &LevelFile,
+    palette: &Palette,
+    max_states: usize,
+) -> Result<SolverResult, LevelLoadError> {
+    Ok(Puzzle::new(level, palette)?.solve(max_states))
+}
+
+type State = Vec<(Dis2, Rotation)>;
+
+/// Where the blocks have to be for one way of routing every input to an output, as the placements
+/// needed from each group of identical blocks. The other blocks only have to stay out of the way.
+type Goal = Vec<Vec<(Dis2, Rotation)>>;
+
+/// How many goals the search heads for at once.
+const MAX_GOALS: usize = 64;
+
+/// How much more the estimated moves left count than the moves made, when choosing which
+/// arrangement to try next. The higher, the faster a solution is found, but the more moves it may
+/// take.
+const GREED: usize = 2;
+
+const ROTATIONS: [Rotation; 4] = [Rotation::D0, Rotation::D90, Rotation::D180, Rotation::D270];
+
+/// The state of the search for goals: the level with the routes laid out so far, and the blocks
+/// they use.
+struct RouteSearch {
+    level: Level,
+    placed: Vec<Option<(Dis2, Rotation)>>,
+    /// How many tiles of belt are left to extend the routes with.
+    spare: usize,
+    goals: Vec<Goal>,
+    explored: usize,
+    max_states: usize,
+}
+
+pub struct Puzzle {
+    level: Level,
+    blocks: Vec<Block>,
+    shapes: Vec<ObjectShape>,
+    /// The blocks grouped by the object they were made from. Blocks in the same group can swap
+    /// places without changing the puzzle, so locked blocks are always on their own.
+    groups: Vec<Vec<usize>>,
+}
+
+impl Puzzle {
+    pub fn new(level: &LevelFile, palette: &Palette) -> Result<Self, LevelLoadError> {
+        let mut layout = level_parse(level, palette)?;
+        let kicks = level.kicks.clone().unwrap_or_default();
+        level_surround(&mut layout);
+
+        let width = layout.iter().map(|row| row.len()).max().unwrap_or_default();
+        let mut level = Level::new(width, layout.len());
+        level.kicks = kicks;
+        let mut blocks = vec![];
+        let mut shapes = vec![];
+        let mut objects: Vec<Object> = vec![];
+        let mut groups: Vec<Vec<usize>> = vec![];
+        for (j, row) in layout.into_iter().enumerate() {
+            for (i, tile) in row.into_iter().enumerate() {
+                let dis: Dis2 = (i, j).into();
+                match tile {
+                    Tile::Floor(Object::Empty) | Tile::Loadingbay => level.set_floor(i, j),
+                    Tile::Floor(object) => {
+                        // Objects have already been validated by `level_parse`
+                        let shape = ObjectShape::new(&object).unwrap();
+                        let mut block = Block::new(level.next_index(), dis);
+                        block.tiles = shape.tiles.clone();
+                        block.lock = object.lock();
+                        level.place_unchecked(&block);
+                        let free = object.lock() == Lock::Free;
+                        match objects.iter().position(|o| free && *o == object) {
+                            Some(group) => groups[group].push(blocks.len()),
+                            None => {
+                                objects.push(object);
+                                groups.push(vec![blocks.len()]);
+                            }
+                        }
+                        blocks.push(block);
+                        shapes.push(shape);
+                    }
+                    Tile::Input(rot, settings) => {
+                        for color in settings.colors {
+                            level.add_input(input_edge(rot, dis), color);
+                        }
+                    }
+                    Tile::Output(rot, color) => level.add_output(output_edge(rot, dis), color),
+                    _ => {}
+                }
+            }
+        }
+        blocks.iter().for_each(|b| level.remove(b));
+        Ok(Self {
+            level,
+            blocks,
+            shapes,
+            groups,
+        })
+    }
+
+    fn start(&self) -> State {
+        self.blocks
+            .iter()
+            .map(|b| (b.position(), b.rotation()))
+            .collect()
+    }
+
+    fn arrange(&self, state: &State) -> (Level, Vec<Block>) {
+        let mut level = self.level.clone();
+        let mut blocks = self.blocks.clone();
+        for (block, (position, rotation)) in blocks.iter_mut().zip(state.iter()) {
+            block.translate(*position).rotate(*rotation);
+            level.place_unchecked(block);
+        }
+        (level, blocks)
+    }
+
+    /// The moves a block can make on its own: a step in any direction, or a turn either way, as far
+    /// as its lock allows.
+    pub fn moves<'a>(
+        level: &'a Level,
+        block: &'a Block,
+    ) -> impl Iterator<Item = (Dis2, Rotation)> + 'a {
+        let position = block.position();
+        let rotation = block.rotation();
+        [Dis2::X, Dis2::NEG_X, Dis2::Z, Dis2::NEG_Z]
+            .into_iter()
+            .map(move |d| (position + d, rotation))
+            .filter(|(p, r)| level.try_place(block, *p, *r))
+            .chain(
+                [Turn::Left, Turn::Right]
+                    .into_iter()
+                    .filter_map(|t| level.try_rotate(block, t)),
+            )
+            .filter(move |to| block.lock.allows((position, rotation), *to))
+    }
+
+    /// Where a block can get to in one move, and how many moves that counts as in the game: every
+    /// position it can slide to without turning, one per tile on the way, or a turn either way.
+    pub fn slides(level: &Level, block: &Block) -> Vec<((Dis2, Rotation), usize)> {
+        let start = (block.position(), block.rotation());
+        let mut distance = HashMap::from([(start.0, 0)]);
+        let mut queue = VecDeque::from([start.0]);
+        let mut slides = vec![];
+        while let Some(pos) = queue.pop_front() {
+            let moves = distance[&pos] + 1;
+            for step in [Dis2::X, Dis2::NEG_X, Dis2::Z, Dis2::NEG_Z] {
+                let next = (pos + step, start.1);
+                if !distance.contains_key(&next.0)
+                    && block.lock.allows(start, next)
+                    && level.try_place(block, next.0, next.1)
+                {
+                    distance.insert(next.0, moves);
+                    queue.push_back(next.0);
+                    slides.push((next, moves));
+                }
+            }
+        }
+        slides.extend(
+            [Turn::Left, Turn::Right]
+                .into_iter()
+                .filter_map(|t| level.try_rotate(block, t))
+                .filter(|to| block.lock.allows(start, *to))
+                .map(|to| (to, 1)),
+        );
+        slides
+    }
+
+    /// Put the placements of identical blocks in a fixed order, so arrangements that only differ
+    /// by which of them is where are only explored once.
+    fn canonical(&self, mut state: State) -> State {
+        for group in self.groups.iter().filter(|g| g.len() > 1) {
+            let mut placements = group.iter().map(|i| state[*i]).collect::<Vec<_>>();
+            placements.sort();
+            for (i, placement) in group.iter().zip(placements) {
+                state[*i] = placement;
+            }
+        }
+        state
+    }
+
+    /// Check whether the level is already solved in its initial arrangement.
+    pub fn is_solved(&self) -> bool {
+        self.connected(&self.blocks)
+    }
+
+    /// Check whether the pieces pass cubes around in a loop in the initial arrangement.
+    pub fn has_cycle(&self) -> bool {
+        self.routing(&self.blocks).has_cycle()
+    }
+
+    /// Check that every output is fed by inputs, and only receives cubes of its own color.
+    pub fn connected(&self, blocks: &[Block]) -> bool {
+        self.routing(blocks).is_solved()
+    }
+
+    fn routing(&self, blocks: &[Block]) -> Routing {
+        let pieces = blocks
+            .iter()
+            .zip(self.shapes.iter())
+            .map(|(b, s)| (b.position(), b.rotation(), s))
+            .collect::<Vec<_>>();
+        Routing::new(&self.level, &pieces)
+    }
+
+    /// Find ways to lay out the blocks so every input is routed to an output, ignoring whether the
+    /// blocks can get there. Gives up after `max_states` steps, like the search for moves.
+    fn goals(&self, max_states: usize) -> RouteSearch {
+        let mut level = self.level.clone();
+        // Fixed blocks are in the way wherever the routes go
+        self.blocks
+            .iter()
+            .filter(|b| b.lock == Lock::Fixed)
+            .for_each(|b| level.place_unchecked(b));
+        let mut search = RouteSearch {
+            level,
+            placed: vec![None; self.blocks.len()],
+            spare: self
+                .blocks
+                .iter()
+                .zip(self.shapes.iter())
+                .filter(|(_, s)| !s.entries.is_empty())
+                .map(|(b, _)| b.tiles.len())
+                .sum(),
+            goals: vec![],
+            explored: 0,
+            max_states,
+        };
+        self.route(&mut search, self.level.inputs().to_vec());
+        search
+    }
+
+    /// Extend the routes from the last of the open ends, with every block that fits there.
+    fn route(&self, search: &mut RouteSearch, mut ends: Vec<(Edge, CubeColor)>) {
+        search.explored += 1;
+        if search.goals.len() >= MAX_GOALS || search.explored >= search.max_states {
+            return;
+        }
+        let placed = |search: &RouteSearch| {
+            search
+                .placed
+                .iter()
+                .enumerate()
+                .filter_map(|(i, p)| p.map(|(position, rotation)| (i, position, rotation)))
+                .collect::<Vec<_>>()
+        };
+        let Some((edge, color)) = ends.pop() else {
+            let pieces = placed(search)
+                .into_iter()
+                .map(|(i, position, rotation)| (position, rotation, &self.shapes[i]))
+                .collect::<Vec<_>>();
+            let routing = Routing::new(&self.level, &pieces);
+            if routing.is_solved() && !routing.has_cycle() {
+                search.goals.push(
+                    self.groups
+                        .iter()
+                        .map(|g| g.iter().filter_map(|i| search.placed[*i]).collect())
+                        .collect(),
+                );
+            }
+            return;
+        };
+        if let Some((_, expected)) = self.level.outputs().iter().find(|(e, _)| *e == edge) {
+            if *expected == color {
+                self.route(search, ends);
+            }
+            return;
+        }
+        // The route runs into one that has already been laid out, through a junction
+        let joins = placed(search).into_iter().any(|(i, position, rotation)| {
+            self.shapes[i].entries.iter().any(|(from, to)| {
+                (
+                    position + from.rotated(rotation),
+                    position + to.rotated(rotation),
+                ) == edge
+            })
+        });
+        if joins {
+            self.route(search, ends);
+            return;
+        }
+        // Unless it can end in a junction, every open end needs a tile of belt for every step
+        // towards an output
+        let junctions = placed(search)
+            .into_iter()
+            .any(|(i, _, _)| self.shapes[i].entries.len() > 1);
+        let too_far = |(_, to): &Edge| {
+            self.level
+                .outputs()
+                .iter()
+                .all(|((from, _), _)| from.distance(*to) as usize >= search.spare)
+        };
+        if !junctions && (too_far(&edge) || ends.iter().any(|(e, _)| too_far(e))) {
+            return;
+        }
+
+        for group in &self.groups {
+            let Some(&i) = group.iter().find(|i| search.placed[**i].is_none()) else {
+                continue;
+            };
+            let mut block = self.blocks[i].clone();
+            let shape = &self.shapes[i];
+            let start = (block.position(), block.rotation());
+            for (from, to) in &shape.entries {
+                for rotation in ROTATIONS {
+                    let position = edge.1 - to.rotated(rotation);
+                    if position + from.rotated(rotation) != edge.0
+                        || !block.lock.allows(start, (position, rotation))
+                        || !search.level.try_place(&block, position, rotation)
+                    {
+                        continue;
+                    }
+                    block.translate(position).rotate(rotation);
+                    if block.lock != Lock::Fixed {
+                        search.level.place(&block);
+                    }
+                    search.placed[i] = Some((position, rotation));
+                    search.spare -= block.tiles.len();
+
+                    let color = shape.painter.map_or(color, |p| p.paint(color));
+                    let mut ends = ends.clone();
+                    ends.extend(shape.exits.iter().map(|(from, to)| {
+                        let edge = (
+                            position + from.rotated(rotation),
+                            position + to.rotated(rotation),
+                        );
+                        (edge, color)
+                    }));
+                    self.route(search, ends);
+
+                    search.spare += block.tiles.len();
+                    search.placed[i] = None;
+                    if block.lock != Lock::Fixed {
+                        search.level.remove(&block);
+                    }
+                }
+            }
+        }
+    }
+
+    /// An estimate of the moves needed to reach the goal: every block has to get to its place one
+    /// tile or turn at a time.
+    fn distance(&self, state: &State, goal: &Goal) -> usize {
+        self.groups
+            .iter()
+            .zip(goal.iter())
+            .map(|(group, targets)| {
+                let placements = group.iter().map(|i| state[*i]).collect::<Vec<_>>();
+                assign(&placements, targets)
+            })
+            .sum()
+    }
+
+    /// Search for a solution, by first finding where the blocks have to go to route every input,
+    /// and then moving them there. The search heads for the nearest of those goals greedily, so
+    /// the solution found may take more moves than necessary.
+    pub fn solve(&self, max_states: usize) -> SolverResult {
+        let RouteSearch {
+            goals,
+            mut explored,
+            ..
+        } = self.goals(max_states);
+        if explored >= max_states {
+            return SolverResult::GaveUp { explored };
+        }
+        if goals.is_empty() {
+            return SolverResult::Unsolvable { explored };
+        }
+        let estimate = |state: &State| {
+            goals
+                .iter()
+                .map(|g| self.distance(state, g))
+                .min()
+                .unwrap_or_default()
+        };
+
+        let start = self.canonical(self.start());
+        let mut best = HashMap::from([(start.clone(), 0)]);
+        let mut queue = BinaryHeap::from([(Reverse(0), 0, start)]);
+        while let Some((_, moves, state)) = queue.pop() {
+            if best[&state] < moves {
+                continue;
+            }
+            let (level, blocks) = self.arrange(&state);
+            if self.connected(&blocks) {
+                return SolverResult::Solved(Solution {
+                    placements: blocks
+                        .iter()
+                        .map(|b| Placement {
+                            index: b.index,
+                            position: b.position(),
+                            rotation: b.rotation(),
+                        })
+                        .collect(),
+                    moves,
+                });
+            }
+            explored += 1;
+            if explored >= max_states {
+                return SolverResult::GaveUp { explored };
+            }
+            for (i, block) in blocks.iter().enumerate() {
+                for (next, cost) in Self::slides(&level, block) {
+                    let mut next_state = state.clone();
+                    next_state[i] = next;
+                    let next_state = self.canonical(next_state);
+                    let moves = moves + cost;
+                    if best.get(&next_state).is_some_and(|m| *m <= moves) {
+                        continue;
+                    }
+                    best.insert(next_state.clone(), moves);
+                    // Ties go to the arrangement furthest along
+                    let estimate = moves + GREED * estimate(&next_state);
+                    queue.push((Reverse(estimate), moves, next_state));
+                }
+            }
+        }
+        SolverResult::Unsolvable { explored }
+    }
+}
+
+/// The fewest moves and turns it takes to get the blocks to the targets, whichever block goes
+/// where.
+fn assign(placements: &[(Dis2, Rotation)], targets: &[(Dis2, Rotation)]) -> usize {
+    let Some(((position, rotation), targets)) = targets.split_first() else {
+        return 0;
+    };
+    (0..placements.len())
+        .map(|i| {
+            let mut others = placements.to_vec();
+            let (p, r) = others.swap_remove(i);
+            p.distance(*position) as usize + turns(r, *rotation) + assign(&others, targets)
+        })
+        .min()
+        .unwrap_or(usize::MAX)
+}
+
+/// How many quarter turns it takes from one rotation to the other.
+fn turns(from: Rotation, to: Rotation) -> usize {
+    let left = ROTATIONS
+        .iter()
+        .cycle()
+        .skip_while(|r| **r != from)
+        .position(|r| *r == to)
+        .unwrap_or_default();
+    left.min(4 - left)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cubes::CubeColor;
+    use crate::game::Lock;
+
+    fn level(layout: &[&str], belts: &[&str]) -> LevelFile {
+        LevelFile {
+            layout: layout.iter().map(|s| s.to_string()).collect(),
+            objects: belts.iter().map(|s| Object::Belt(s.to_string())).collect(),
+            par: None,
+            objectives: vec![],
+            inputs: Default::default(),
+            outputs: Default::default(),
+            kicks: None,
+        }
+    }
+
+    #[test]
+    fn test_solvable() {
+        let level = level(&["#o##", "#  #", "#  #", "# 0#", "#i##"], &["fff"]);
+        match solve(&level, &Palette::default(), 1000).unwrap() {
+            SolverResult::Solved(solution) => {
+                assert_eq!(solution.moves, 1);
+                assert_eq!(
+                    solution.placements,
+                    vec![Placement {
+                        index: 1,
+                        position: Dis2::new(1, 3),
+                        rotation: Rotation::D0
+                    }]
+                );
+            }
+            res => panic!("Expected a solution, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_junctions() {
+        let mut splitter = level(&["###", "o0o", "#i#"], &[]);
+        splitter.objects = vec![Object::Splitter];
+        assert!(Puzzle::new(&splitter, &Palette::default())
+            .unwrap()
+            .is_solved());
+
+        let mut merger = level(&["#o#", "i0i", "###"], &[]);
+        merger.objects = vec![Object::Merger];
+        assert!(Puzzle::new(&merger, &Palette::default())
+            .unwrap()
+            .is_solved());
+
+        // Both sides of a merger must carry the right color
+        merger.layout[1] = "i0I".to_string();
+        assert!(!Puzzle::new(&merger, &Palette::default())
+            .unwrap()
+            .is_solved());
+
+        let mut painter = level(&["#o#", "#0#", "#I#"], &[]);
+        painter.objects = vec![Object::Painter(CubeColor::GREEN, CubeColor::PURPLE)];
+        assert!(Puzzle::new(&painter, &Palette::default())
+            .unwrap()
+            .is_solved());
+
+        // Other colors pass through unchanged
+        painter.objects = vec![Object::Painter(CubeColor::PURPLE, CubeColor::GREEN)];
+        assert!(!Puzzle::new(&painter, &Palette::default())
+            .unwrap()
+            .is_solved());
+    }
+
+    #[test]
+    fn test_locks() {
+        let mut level = level(&["#o##", "#  #", "#  #", "# 0#", "#i##"], &[]);
+        let locked = |lock| Object::Locked(lock, Box::new(Object::Belt("fff".to_string())));
+        level.objects = vec![locked(Lock::SlideX)];
+        match solve(&level, &Palette::default(), 1000).unwrap() {
+            SolverResult::Solved(solution) => assert_eq!(solution.moves, 1),
+            res => panic!("Expected a solution, got {:?}", res),
+        }
+        for lock in [Lock::SlideZ, Lock::Fixed] {
+            level.objects = vec![locked(lock)];
+            assert!(matches!(
+                solve(&level, &Palette::default(), 1000).unwrap(),
+                SolverResult::Unsolvable { .. }
+            ));
+        }
+    }
+
+    #[test]
+    fn test_crates() {
+        // The crate has to be pushed into the alcove to make room for the belt
+        let mut level = level(&["#o##", "#  #", "#  #", " 10#", "#i##"], &["fff"]);
+        level.objects.push(Object::Crate(vec![(0, 0)]));
+        match solve(&level, &Palette::default(), 1000).unwrap() {
+            SolverResult::Solved(solution) => assert_eq!(solution.moves, 2),
+            res => panic!("Expected a solution, got {:?}", res),
+        }
+        level.objects[1] = Object::Locked(Lock::Fixed, Box::new(Object::Crate(vec![(0, 0)])));
+        assert!(matches!(
+            solve(&level, &Palette::default(), 1000).unwrap(),
+            SolverResult::Unsolvable { .. }
+        ));
+    }
+
+    #[test]
+    fn test_identical_blocks() {
+        // Either belt can go first, which should not double the arrangements to explore
+        let level = level(&["#o##", "# 0#", "# 0#", "#i##"], &["f"]);
+        let puzzle = Puzzle::new(&level, &Palette::default()).unwrap();
+        assert_eq!(puzzle.groups, vec![vec![0, 1]]);
+        match puzzle.solve(1000) {
+            SolverResult::Solved(solution) => assert_eq!(solution.moves, 2),
+            res => panic!("Expected a solution, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_campaign_level() {
+        let palette =
+            ron::from_str(&std::fs::read_to_string("assets/colors.palette.ron").unwrap()).unwrap();
+        let level: LevelFile =
+            ron::from_str(&std::fs::read_to_string("assets/levels/level_01.ron").unwrap()).unwrap();
+        assert!(matches!(
+            solve(&level, &palette, 100_000).unwrap(),
+            SolverResult::Solved(_)
+        ));
+    }
+
+    #[test]
+    fn test_unsolvable() {
+        let level = level(&["#O##", "#  #", "#  #", "# 0#", "#i##"], &["fff"]);
+        assert!(matches!(
+            solve(&level, &Palette::default(), 1000).unwrap(),
+            SolverResult::Unsolvable { .. }
+        ));
+    }
+}
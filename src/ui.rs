@@ -1,6 +1,11 @@
+use bevy::ecs::system::EntityCommands;
 use bevy::prelude::*;
 
-use crate::levels::{LevelEntity, LevelState};
+use crate::cubes::{Simulation, SimulationEvent};
+use crate::game::HistoryEvent;
+use crate::levels::{LeaveLevel, LevelEntity, LevelState, PuzzleSeed};
+use crate::score::Score;
+use crate::select::LevelSelectButton;
 use crate::AppState;
 
 pub struct UIPlugin;
@@ -12,12 +17,30 @@ impl Plugin for UIPlugin {
             .add_systems(Update, (button_interaction, show_dialog))
             .add_systems(
                 Update,
-                (next_level_button, despawn_button, show_victory).run_if(in_state(AppState::Level)),
+                (
+                    next_level_button,
+                    despawn_button,
+                    history_button,
+                    simulation_button,
+                    show_simulation_state,
+                    show_victory,
+                )
+                    .run_if(in_state(AppState::Level)),
+            )
+            .add_systems(
+                Update,
+                leave_level_button
+                    .run_if(in_state(AppState::Level).or_else(in_state(AppState::Editor))),
             )
             .add_systems(OnEnter(AppState::Unloading), reset_victory)
             .add_systems(
                 OnEnter(AppState::Loading),
-                setup_main_menu.run_if(in_state(LevelState::MainMenu)),
+                (
+                    setup_main_menu.run_if(in_state(LevelState::MainMenu)),
+                    setup_hud.run_if(
+                        in_state(LevelState::Campaign).or_else(in_state(LevelState::Random)),
+                    ),
+                ),
             );
     }
 }
@@ -35,6 +58,9 @@ impl ShowVictory {
     pub fn show(&mut self) {
         self.victory = true;
     }
+    pub fn won(&self) -> bool {
+        self.victory
+    }
     pub fn disable(&mut self) {
         self.showing = true;
     }
@@ -44,13 +70,23 @@ impl ShowVictory {
 struct NextLevelButton;
 
 #[derive(Component)]
-struct DespawnButton(Entity);
+pub struct DespawnButton(pub Entity);
+
+#[derive(Component)]
+struct HistoryButton(HistoryEvent);
+
+#[derive(Component)]
+pub struct LeaveLevelButton(pub LevelState);
+
+#[derive(Component)]
+struct SimulationButton(SimulationEvent);
 
 const NORMAL_BUTTON: Color = Color::WHITE;
 const HOVERED_BUTTON: Color = Color::rgb(0.85, 0.95, 1.00);
 const PRESSED_BUTTON: Color = Color::rgb(0.9, 1.00, 1.00);
 const BORDER_BUTTON: Color = Color::BLACK;
-const PANEL_COLOR: Color = Color::rgba(1.0, 1.0, 1.0, 0.5);
+const ACTIVE_BORDER_BUTTON: Color = Color::rgb(0.1, 0.5, 1.0);
+pub const PANEL_COLOR: Color = Color::rgba(1.0, 1.0, 1.0, 0.5);
 
 fn button_interaction(
     mut interaction_query: Query<(&Interaction, &mut BackgroundColor), Changed<Interaction>>,
@@ -104,6 +140,73 @@ fn despawn_button(
     }
 }
 
+fn leave_level_button(
+    interaction_query: Query<(&Interaction, &LeaveLevelButton), Changed<Interaction>>,
+    mut leave: EventWriter<LeaveLevel>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    for (interaction, button) in interaction_query.iter() {
+        if matches!(interaction, Interaction::Pressed) {
+            leave.send(LeaveLevel(button.0));
+            next_state.set(AppState::Unloading);
+            return;
+        }
+    }
+}
+
+fn history_button(
+    interaction_query: Query<(&Interaction, &HistoryButton), Changed<Interaction>>,
+    mut event: EventWriter<HistoryEvent>,
+) {
+    for (interaction, button) in interaction_query.iter() {
+        if matches!(interaction, Interaction::Pressed) {
+            event.send(button.0);
+        }
+    }
+}
+
+fn simulation_button(
+    interaction_query: Query<(&Interaction, &SimulationButton), Changed<Interaction>>,
+    mut event: EventWriter<SimulationEvent>,
+) {
+    for (interaction, button) in interaction_query.iter() {
+        if matches!(interaction, Interaction::Pressed) {
+            event.send(button.0);
+        }
+    }
+}
+
+/// Label the pause button with what it does, and outline the current speed.
+fn show_simulation_state(
+    simulation: Res<Simulation>,
+    mut buttons: Query<(&SimulationButton, &mut BorderColor, &Children)>,
+    mut texts: Query<&mut Text>,
+) {
+    for (button, mut border, children) in buttons.iter_mut() {
+        match button.0 {
+            SimulationEvent::TogglePause => {
+                let label = if simulation.paused() { "Play" } else { "Pause" };
+                for &child in children.iter() {
+                    if let Ok(mut text) = texts.get_mut(child) {
+                        if text.sections[0].value != label {
+                            text.sections[0].value = label.to_string();
+                        }
+                    }
+                }
+            }
+            SimulationEvent::Speed(speed) => {
+                let active = !simulation.paused() && simulation.speed() == speed;
+                border.0 = if active {
+                    ACTIVE_BORDER_BUTTON
+                } else {
+                    BORDER_BUTTON
+                };
+            }
+            SimulationEvent::Step => {}
+        }
+    }
+}
+
 fn show_dialog(
     mut event: EventReader<ShowDialog>,
     mut cmds: Commands,
@@ -116,12 +219,15 @@ fn show_dialog(
 
 fn show_victory(
     mut victory: ResMut<ShowVictory>,
+    mut score: ResMut<Score>,
+    time: Res<Time>,
     mut cmds: Commands,
     asset_server: Res<AssetServer>,
 ) {
     if victory.victory && !victory.showing {
+        score.finish(time.elapsed_seconds());
         dialog(
-            "All routes connected and verified!",
+            &format!("All routes connected and verified!\n\n{}", score.summary()),
             true,
             &mut cmds,
             &asset_server,
@@ -282,6 +388,119 @@ fn setup_main_menu(mut cmds: Commands, asset_server: Res<AssetServer>) {
                         },
                     ));
                 });
+            spawn_button(parent, "Select level", 220.0, &asset_server).insert(LevelSelectButton);
+            spawn_button(parent, "Random puzzle", 220.0, &asset_server)
+                .insert(LeaveLevelButton(LevelState::Random));
+            spawn_button(parent, "Editor", 220.0, &asset_server)
+                .insert(LeaveLevelButton(LevelState::Editor));
         });
     });
 }
+
+pub fn spawn_button<'w, 's, 'a>(
+    parent: &'a mut ChildBuilder<'w, 's, '_>,
+    text: &str,
+    width: f32,
+    asset_server: &Res<AssetServer>,
+) -> EntityCommands<'w, 's, 'a> {
+    let mut button = parent.spawn(ButtonBundle {
+        style: Style {
+            width: Val::Px(width),
+            height: Val::Px(45.0),
+            border: UiRect::all(Val::Px(3.0)),
+            // horizontally center child text
+            justify_content: JustifyContent::Center,
+            // vertically center child text
+            align_items: AlignItems::Center,
+            ..default()
+        },
+        border_color: BORDER_BUTTON.into(),
+        background_color: NORMAL_BUTTON.into(),
+        ..default()
+    });
+    button.with_children(|parent| {
+        parent.spawn(TextBundle::from_section(
+            text,
+            TextStyle {
+                font: asset_server.load("fonts/Tourney-Medium.ttf"),
+                font_size: 32.0,
+                color: Color::BLACK,
+            },
+        ));
+    });
+    button
+}
+
+fn setup_hud(
+    mut cmds: Commands,
+    asset_server: Res<AssetServer>,
+    level: Res<State<LevelState>>,
+    seed: Res<PuzzleSeed>,
+) {
+    cmds.spawn((
+        LevelEntity,
+        NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                top: Val::Px(10.0),
+                right: Val::Px(10.0),
+                flex_direction: FlexDirection::Row,
+                column_gap: Val::Px(10.0),
+                padding: UiRect::all(Val::Px(10.0)),
+                ..default()
+            },
+            background_color: PANEL_COLOR.into(),
+            ..default()
+        },
+    ))
+    .with_children(|parent| {
+        if **level == LevelState::Random {
+            parent.spawn(
+                TextBundle::from_section(
+                    seed.to_string(),
+                    TextStyle {
+                        font: asset_server.load("fonts/Tourney-Medium.ttf"),
+                        font_size: 32.0,
+                        color: Color::BLACK,
+                    },
+                )
+                .with_style(Style {
+                    align_self: AlignSelf::Center,
+                    ..default()
+                }),
+            );
+        }
+        for (text, event) in [("Undo", HistoryEvent::Undo), ("Redo", HistoryEvent::Redo)] {
+            spawn_button(parent, text, 120.0, &asset_server).insert(HistoryButton(event));
+        }
+        spawn_button(parent, "Menu", 120.0, &asset_server)
+            .insert(LeaveLevelButton(LevelState::MainMenu));
+    });
+    cmds.spawn((
+        LevelEntity,
+        NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                bottom: Val::Px(10.0),
+                right: Val::Px(10.0),
+                flex_direction: FlexDirection::Row,
+                column_gap: Val::Px(10.0),
+                padding: UiRect::all(Val::Px(10.0)),
+                ..default()
+            },
+            background_color: PANEL_COLOR.into(),
+            ..default()
+        },
+    ))
+    .with_children(|parent| {
+        for (text, event) in [
+            ("Pause", SimulationEvent::TogglePause),
+            ("Step", SimulationEvent::Step),
+            ("1x", SimulationEvent::Speed(1)),
+            ("2x", SimulationEvent::Speed(2)),
+            ("4x", SimulationEvent::Speed(4)),
+        ] {
+            spawn_button(parent, text, 90.0, &asset_server).insert(SimulationButton(event));
+        }
+    });
+}